@@ -1,269 +1,2034 @@
+use std::{
+    collections::BTreeMap,
+    fmt, hint,
+    io::{self, BufWriter, Write},
+    iter::FusedIterator,
+    marker::PhantomData,
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::Instant,
+};
+
 const SAMPLE_INPUT: &str = "11-22,95-115,998-1012,1188511880-1188511890,222220-222224,1698522-1698528,446443-446449,38593856-38593862,565653-565659,824824821-824824827,2121212118-2121212124";
 
 fn main() {
-    let id_ranges = IdRange::parse_multiple(SAMPLE_INPUT);
+    let args: Vec<String> = std::env::args().collect();
+
+    let radix = flag_value(&args, "--radix", "a number from 2 to 36", |n| {
+        n.parse::<u32>()
+            .ok()
+            .filter(|radix| IdRange::RADIXES.contains(radix))
+    });
+
+    let mut id_ranges = match radix {
+        Some(radix) => IdRange::parse_multiple_with_radix(SAMPLE_INPUT, radix),
+        None => IdRange::parse_multiple(SAMPLE_INPUT),
+    };
+
+    if args.iter().any(|arg| arg == "--fixed-width") {
+        id_ranges = id_ranges.into_iter().map(IdRange::fixed_width).collect();
+    }
+
+    // IDs given to and shown by the queries are in the radix of the ranges
+    let id_radix = radix.unwrap_or(IdRange::DEFAULT_RADIX);
+    let invalid_ids = InvalidIds::new(id_radix);
+
+    let id_argument = |mode: &str| {
+        args.get(2)
+            .and_then(|id| u128::from_str_radix(id, id_radix).ok())
+            .unwrap_or_else(|| panic!("{mode} must be followed by an ID in radix {id_radix}"))
+    };
+
+    match args.get(1).map(String::as_str) {
+        Some("explain") => print!("{}", InvalidIdReport::build(&id_ranges)),
+        Some("export") => {
+            let format = args
+                .get(2)
+                .and_then(|name| ExportFormat::parse(name))
+                .expect("Export format must be csv or json");
+
+            let mut out = BufWriter::new(io::stdout().lock());
+
+            export_invalids(&id_ranges, format, &mut out).unwrap();
+            out.flush().unwrap();
+        }
+        Some("stats") => print!("{}", InvalidIdStatistics::build(&id_ranges)),
+        Some("bench") => bench(&id_ranges),
+        Some("count") => {
+            let count: u128 = id_ranges.iter().map(IdRange::count_invalids).sum();
+
+            println!("The count is: {count}");
+        }
+        Some("nearest") => {
+            let id = id_argument("nearest");
+
+            for id_range in &id_ranges {
+                let NearestValidIds { below, above } = id_range.nearest_valid(id);
+
+                let describe = |nearest: Option<u128>| match nearest {
+                    Some(nearest) => id_range.format_id(nearest),
+                    None => "none".to_string(),
+                };
+
+                println!(
+                    "{id_range}: {} below, {} above",
+                    describe(below),
+                    describe(above)
+                );
+            }
+        }
+        Some("nth") => {
+            let k = args
+                .get(2)
+                .and_then(|k| k.parse::<u128>().ok())
+                .expect("nth must be followed by a number");
+
+            match invalid_ids.nth(k) {
+                Some(id) => println!("Invalid ID {k} is: {}", to_radix_string(id, id_radix)),
+                None => println!("There is no invalid ID {k}"),
+            }
+        }
+        Some("below") => {
+            let id = id_argument("below");
+
+            println!(
+                "Invalid IDs below {}: {}",
+                to_radix_string(id, id_radix),
+                invalid_ids.count_below(id)
+            );
+        }
+        Some("next") => {
+            let id = id_argument("next");
+
+            match invalid_ids.next_after(id) {
+                Some(next) => println!(
+                    "The next invalid ID is: {}",
+                    to_radix_string(next, id_radix)
+                ),
+                None => println!(
+                    "There is no invalid ID after {}",
+                    to_radix_string(id, id_radix)
+                ),
+            }
+        }
+        _ => {
+            let num_threads = flag_value(&args, "--threads", "a number", |n| n.parse().ok());
+
+            let sum_of_invalids = match num_threads {
+                Some(num_threads) => parallel_sum_invalids(&id_ranges, num_threads),
+                None => sum_invalids(&id_ranges),
+            };
+
+            match sum_of_invalids {
+                Some(sum_of_invalids) => println!("The sum is: {}", sum_of_invalids),
+                None => println!("The sum overflowed"),
+            }
+        }
+    }
+}
 
-    let all_invalids = id_ranges
+/// Parses the value after the first `flag`, or `None` if it was not given. Panics with what was
+/// `expected` if the value is missing or does not parse.
+fn flag_value<'a, T>(
+    args: &'a [String],
+    flag: &str,
+    expected: &str,
+    parse: impl FnOnce(&'a str) -> Option<T>,
+) -> Option<T> {
+    let i = args.iter().position(|arg| arg == flag)?;
+
+    let value = args
+        .get(i + 1)
+        .and_then(|value| parse(value))
+        .unwrap_or_else(|| panic!("{flag} must be followed by {expected}"));
+
+    Some(value)
+}
+
+/// Sums the invalid IDs of all ranges on `num_threads` threads.
+///
+/// The ranges are cut into chunks small enough that every thread gets several, which the threads
+/// then take turns picking up so a thread stuck with a slow chunk does not hold up the others.
+/// The chunk sums are added up in range order once all threads are done.
+fn parallel_sum_invalids(id_ranges: &[IdRange], num_threads: usize) -> Option<u128> {
+    const CHUNKS_PER_THREAD: u128 = 8;
+    const MIN_CHUNK_SIZE: u128 = 1024;
+
+    let num_threads = num_threads.max(1);
+
+    // Saturates, the count only sizes the chunks
+    let num_ids = id_ranges
+        .iter()
+        .map(|id_range| id_range.iter().remaining())
+        .fold(0, u128::saturating_add);
+
+    let chunk_size = (num_ids / (num_threads as u128 * CHUNKS_PER_THREAD)).max(MIN_CHUNK_SIZE);
+
+    let chunks: Vec<IdRangeIterator<u128>> = id_ranges
         .iter()
-        .flat_map(|id_range| id_range.iter().find_invalids())
-        .collect::<Vec<String>>();
+        .flat_map(|id_range| id_range.iter().numbers().chunks(chunk_size))
+        .collect();
+
+    // Threads beyond one per chunk would find nothing to pick up
+    let num_threads = num_threads.min(chunks.len());
+
+    let next_chunk = AtomicUsize::new(0);
+    let overflowed = AtomicBool::new(false);
+
+    let mut chunk_sums = vec![None; chunks.len()];
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut sums = Vec::new();
+
+                    loop {
+                        // The last chunks hold the largest IDs, so taking them first finds an
+                        // overflow soonest
+                        let taken = next_chunk.fetch_add(1, Ordering::Relaxed);
+
+                        let Some(index) = chunks.len().checked_sub(taken + 1) else {
+                            break;
+                        };
+
+                        let sum = chunks[index].sum_invalids_until(&overflowed);
 
-    let sum_of_invalids = all_invalids
+                        if sum.is_none() {
+                            overflowed.store(true, Ordering::Relaxed);
+                        }
+
+                        sums.push((index, sum));
+                    }
+
+                    sums
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            for (index, sum) in worker.join().unwrap() {
+                chunk_sums[index] = sum;
+            }
+        }
+    });
+
+    chunk_sums
+        .into_iter()
+        .try_fold(0, |sum: u128, chunk_sum| sum.checked_add(chunk_sum?))
+}
+
+/// Sums the invalid IDs of all ranges, or `None` if the sum does not fit.
+fn sum_invalids(id_ranges: &[IdRange]) -> Option<u128> {
+    id_ranges.iter().try_fold(0, |sum: u128, id_range| {
+        sum.checked_add(id_range.sum_invalids()?)
+    })
+}
+
+/// The original way of summing, going through a `String` for every ID in the ranges.
+fn sum_invalids_via_strings(id_ranges: &[IdRange]) -> u128 {
+    id_ranges
         .iter()
-        .map(|i| i.parse::<u64>().unwrap())
-        .sum::<u64>();
+        .flat_map(|id_range| {
+            id_range
+                .iter()
+                .find_invalids()
+                .into_iter()
+                .map(|i| u128::from_str_radix(&i, id_range.radix).unwrap())
+        })
+        .sum()
+}
+
+fn bench(id_ranges: &[IdRange]) {
+    const ITERATIONS: u32 = 200;
+
+    let time = |name: &str, sum_invalids: &dyn Fn(&[IdRange]) -> Option<u128>| {
+        let started = Instant::now();
+
+        let mut sum = None;
+        for _ in 0..ITERATIONS {
+            sum = sum_invalids(hint::black_box(id_ranges));
+        }
+
+        let per_iteration = started.elapsed() / ITERATIONS;
+        println!("{name:>8}: {per_iteration:?} per iteration (sum {sum:?})");
+    };
+
+    time("strings", &|id_ranges| {
+        Some(sum_invalids_via_strings(id_ranges))
+    });
+    time("integers", &sum_invalids);
+}
+
+#[derive(PartialEq, Debug)]
+struct IdRange<'a> {
+    start: &'a str,
+    end: &'a str,
+    radix: u32,
+    width: IdWidth,
+}
+
+/// How the digits of an ID are compared when checking whether it is invalid.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum IdWidth {
+    /// IDs are numbers, so leading zeros are not part of them.
+    Natural,
+    /// IDs are always zero-padded to this many digits.
+    Fixed(usize),
+}
+
+impl IdWidth {
+    fn min_digits(&self) -> usize {
+        match self {
+            IdWidth::Natural => 1,
+            IdWidth::Fixed(width) => *width,
+        }
+    }
+}
+
+/// Iterates the IDs of a range, either as digit strings or, through [`IdRangeIterator::numbers`], as numbers.
+#[derive(Clone)]
+struct IdRangeIterator<T = String> {
+    current: u128,
+    end: u128,
+    exhausted: bool,
+    radix: u32,
+    width: usize,
+    item: PhantomData<T>,
+}
+
+/// What an [`IdRangeIterator`] can yield for each ID.
+trait IdItem {
+    fn from_id(id: u128, radix: u32, width: usize) -> Self;
+}
+
+impl IdItem for String {
+    fn from_id(id: u128, radix: u32, width: usize) -> Self {
+        to_padded_radix_string(id, radix, width)
+    }
+}
+
+impl IdItem for u128 {
+    fn from_id(id: u128, _radix: u32, _width: usize) -> Self {
+        id
+    }
+}
+
+impl<T> IdRangeIterator<T> {
+    fn new(start: u128, end: u128, radix: u32, width: usize) -> Self {
+        Self {
+            current: start,
+            end,
+            exhausted: start > end,
+            radix,
+            width,
+            item: PhantomData,
+        }
+    }
+
+    fn numbers(self) -> IdRangeIterator<u128> {
+        IdRangeIterator {
+            current: self.current,
+            end: self.end,
+            exhausted: self.exhausted,
+            radix: self.radix,
+            width: self.width,
+            item: PhantomData,
+        }
+    }
+
+    /// How many IDs remain, however many that is. Saturates, since a range covering all of `u128`
+    /// holds one more ID than `u128::MAX`.
+    fn remaining(&self) -> u128 {
+        if self.exhausted {
+            return 0;
+        }
+
+        (self.end - self.current).saturating_add(1)
+    }
+}
+
+impl<T: IdItem> Iterator for IdRangeIterator<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+
+        let value = self.current;
+
+        if self.current == self.end {
+            self.exhausted = true;
+        } else {
+            self.current += 1;
+        }
+
+        Some(T::from_id(value, self.radix, self.width))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        if n as u128 >= self.remaining() {
+            self.exhausted = true;
+            return None;
+        }
+
+        self.current += n as u128;
+
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match usize::try_from(self.remaining()) {
+            Ok(remaining) => (remaining, Some(remaining)),
+            Err(_) => (usize::MAX, None),
+        }
+    }
+
+    /// Panics if more IDs remain than fit a `usize`, like counting them one by one would. Use
+    /// [`IdRangeIterator::remaining`] for ranges that large.
+    fn count(self) -> usize {
+        usize::try_from(self.remaining())
+            .expect("More IDs remain than fit a usize, use remaining() instead")
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<T: IdItem> DoubleEndedIterator for IdRangeIterator<T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+
+        let value = self.end;
+
+        if self.current == self.end {
+            self.exhausted = true;
+        } else {
+            self.end -= 1;
+        }
+
+        Some(T::from_id(value, self.radix, self.width))
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        if n as u128 >= self.remaining() {
+            self.exhausted = true;
+            return None;
+        }
+
+        self.end -= n as u128;
+
+        self.next_back()
+    }
+}
+
+impl<T: IdItem> FusedIterator for IdRangeIterator<T> {}
+
+impl IdRangeIterator<u128> {
+    fn invalids(self) -> impl Iterator<Item = u128> {
+        let radix = self.radix;
+        let width = self.width;
+
+        self.filter(move |id| is_invalid_padded(*id, radix, width))
+    }
+
+    /// Sums the remaining invalid IDs, or `None` if the sum does not fit or `stop` gets set.
+    ///
+    /// Natural IDs jump from one invalid ID to the next through [`InvalidIds`], so even ranges
+    /// far too large to scan are summed as quickly as they overflow.
+    fn sum_invalids_until(&self, stop: &AtomicBool) -> Option<u128> {
+        let stopped = || stop.load(Ordering::Relaxed);
+
+        if self.width > IdWidth::Natural.min_digits() {
+            return self.clone().invalids().try_fold(0, |sum: u128, id| {
+                if stopped() { None } else { sum.checked_add(id) }
+            });
+        }
+
+        if self.exhausted {
+            return Some(0);
+        }
+
+        let invalid_ids = InvalidIds::new(self.radix);
+
+        let mut next = if is_invalid_in_radix(self.current, self.radix) {
+            Some(self.current)
+        } else {
+            invalid_ids.next_after(self.current)
+        };
+
+        let mut sum: u128 = 0;
+
+        while let Some(id) = next.filter(|id| *id <= self.end) {
+            if stopped() {
+                return None;
+            }
+
+            sum = sum.checked_add(id)?;
+            next = invalid_ids.next_after(id);
+        }
+
+        Some(sum)
+    }
+
+    /// Splits the remaining IDs into consecutive iterators of at most `chunk_size` IDs each.
+    fn chunks(self, chunk_size: u128) -> Vec<Self> {
+        let mut chunks = Vec::new();
+
+        if self.exhausted {
+            return chunks;
+        }
+
+        let mut chunk_start = self.current;
+
+        loop {
+            let chunk_end = chunk_start.saturating_add(chunk_size - 1).min(self.end);
+
+            chunks.push(IdRangeIterator::new(
+                chunk_start,
+                chunk_end,
+                self.radix,
+                self.width,
+            ));
+
+            if chunk_end == self.end {
+                break;
+            }
+
+            chunk_start = chunk_end + 1;
+        }
+
+        chunks
+    }
+}
+
+impl IdRangeIterator {
+    fn find_invalids(self) -> Vec<String> {
+        let mut invalids: Vec<String> = Vec::new();
+
+        for id in self.collect::<Vec<String>>() {
+            if is_invalid(&id) {
+                invalids.push(id);
+            }
+        }
+
+        invalids
+    }
+
+    fn explain_invalids(self) -> Vec<ExplainedId> {
+        self.filter_map(ExplainedId::explain).collect()
+    }
+}
+
+impl<'a> IdRange<'a> {
+    const DEFAULT_RADIX: u32 = 10;
+    /// The radixes `char::to_digit` can read digits in.
+    const RADIXES: RangeInclusive<u32> = 2..=36;
+
+    fn check_radix(radix: u32) {
+        assert!(
+            IdRange::RADIXES.contains(&radix),
+            "The radix must be between 2 and 36"
+        );
+    }
+
+    #[cfg(test)]
+    fn parse(input: &'a str) -> Self {
+        IdRange::parse_with_radix(input, IdRange::DEFAULT_RADIX)
+    }
+
+    /// Reads the range's bounds as IDs in `radix`, which must be within `2..=36`. Both bounds
+    /// must be digits in that radix and fit a `u128`.
+    fn parse_with_radix(input: &'a str, radix: u32) -> Self {
+        IdRange::check_radix(radix);
+
+        let split: Vec<&str> = input.split("-").collect();
+
+        let start = split[0];
+        let end = split[1];
+
+        for bound in [start, end] {
+            assert!(
+                u128::from_str_radix(bound, radix).is_ok(),
+                "{bound:?} is not an ID in radix {radix}"
+            );
+        }
+
+        Self {
+            start,
+            end,
+            radix,
+            width: IdWidth::Natural,
+        }
+    }
+
+    /// Compares IDs as zero-padded to the width of the range's bounds, instead of as numbers.
+    fn fixed_width(self) -> Self {
+        let width = IdWidth::Fixed(self.start.len().max(self.end.len()));
+
+        Self { width, ..self }
+    }
+
+    /// Writes `id` in the range's radix, zero-padded like the range compares it.
+    fn format_id(&self, id: u128) -> String {
+        to_padded_radix_string(id, self.radix, self.width.min_digits())
+    }
+
+    fn iter(&self) -> IdRangeIterator {
+        let (start, end) = self.bounds();
+
+        IdRangeIterator::new(start, end, self.radix, self.width.min_digits())
+    }
+
+    fn bounds(&self) -> (u128, u128) {
+        let start = u128::from_str_radix(self.start, self.radix).unwrap();
+        let end = u128::from_str_radix(self.end, self.radix).unwrap();
+
+        (start, end)
+    }
+
+    fn invalid_numbers(&self) -> impl Iterator<Item = u128> {
+        self.iter().numbers().invalids()
+    }
+
+    fn count_invalids(&self) -> u128 {
+        self.invalid_numbers().count() as u128
+    }
+
+    /// The sum of the range's invalid IDs, or `None` if it does not fit.
+    fn sum_invalids(&self) -> Option<u128> {
+        self.iter()
+            .numbers()
+            .sum_invalids_until(&AtomicBool::new(false))
+    }
+
+    /// The valid IDs within the range closest to `id` on either side, as replacements for it.
+    fn nearest_valid(&self, id: u128) -> NearestValidIds {
+        let (start, end) = self.bounds();
+        let width = self.width.min_digits();
+
+        let is_valid = |candidate: &u128| !is_invalid_padded(*candidate, self.radix, width);
+
+        // Invalid IDs are sparse and never follow each other, so stepping over them one at a
+        // time only ever skips a single ID
+        let below = match id.checked_sub(1) {
+            Some(before_id) => IdRangeIterator::new(start, before_id.min(end), self.radix, width)
+                .rev()
+                .find(is_valid),
+            None => None,
+        };
+
+        let above = match id.checked_add(1) {
+            Some(after_id) => {
+                IdRangeIterator::new(after_id.max(start), end, self.radix, width).find(is_valid)
+            }
+            None => None,
+        };
+
+        NearestValidIds { below, above }
+    }
+
+    fn parse_multiple(comma_separated_input: &'a str) -> Vec<Self> {
+        IdRange::parse_multiple_with_radix(comma_separated_input, IdRange::DEFAULT_RADIX)
+    }
+
+    fn parse_multiple_with_radix(comma_separated_input: &'a str, radix: u32) -> Vec<Self> {
+        let splitted = comma_splitter(comma_separated_input);
+
+        splitted
+            .iter()
+            .map(|s| IdRange::parse_with_radix(s, radix))
+            .collect()
+    }
+}
+
+#[derive(PartialEq, Debug)]
+struct NearestValidIds {
+    below: Option<u128>,
+    above: Option<u128>,
+}
+
+impl fmt::Display for IdRange<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+fn comma_splitter(sample_input_short: &str) -> Vec<&str> {
+    sample_input_short.split(",").collect()
+}
+
+/// Enough room for the digits of any `u128`, even in radix 2.
+const MAX_DIGITS: usize = u128::BITS as usize;
+
+/// Writes `value` as lowercase ASCII digits in `radix`, zero-padded to at least `width` digits,
+/// to the end of `buffer` and returns them.
+///
+/// `radix` must be within `2..=36` and `width` at most [`MAX_DIGITS`].
+fn radix_digits(mut value: u128, radix: u32, width: usize, buffer: &mut [u8; MAX_DIGITS]) -> &[u8] {
+    assert!(
+        width <= MAX_DIGITS,
+        "IDs can be at most {MAX_DIGITS} digits wide"
+    );
+
+    let radix_wide = radix as u128;
+
+    let mut start = MAX_DIGITS;
+
+    loop {
+        let digit = (value % radix_wide) as u32;
+
+        start -= 1;
+        buffer[start] = char::from_digit(digit, radix).unwrap() as u8;
+
+        value /= radix_wide;
+
+        if value == 0 {
+            break;
+        }
+    }
+
+    while MAX_DIGITS - start < width {
+        start -= 1;
+        buffer[start] = b'0';
+    }
+
+    &buffer[start..]
+}
+
+fn to_radix_string(value: u128, radix: u32) -> String {
+    to_padded_radix_string(value, radix, 1)
+}
+
+fn to_padded_radix_string(value: u128, radix: u32, width: usize) -> String {
+    let mut buffer = [0; MAX_DIGITS];
+
+    String::from_utf8(radix_digits(value, radix, width, &mut buffer).to_vec()).unwrap()
+}
+
+fn is_invalid(s: &str) -> bool {
+    explain_invalid(s).is_some()
+}
+
+/// Like [`is_invalid`], but checks a number directly without allocating its digit string.
+fn is_invalid_in_radix(id: u128, radix: u32) -> bool {
+    is_invalid_padded(id, radix, 1)
+}
+
+/// Like [`is_invalid_in_radix`], but with the ID zero-padded to at least `width` digits.
+fn is_invalid_padded(id: u128, radix: u32, width: usize) -> bool {
+    let mut buffer = [0; MAX_DIGITS];
+
+    smallest_pattern_length(radix_digits(id, radix, width, &mut buffer)).is_some()
+}
+
+/// All invalid IDs of a radix as one ascending sequence, queried by counting rather than scanning.
+/// IDs are natural numbers here, see [`IdWidth::Natural`].
+///
+/// An ID with `d` digits made of a `p` digit block repeated is the block times `1 0..01 0..01`
+/// (a one every `p` digits), so the invalid IDs below a bound can be counted per block length.
+/// Blocks repeating a smaller block themselves are deduplicated by inclusion-exclusion.
+struct InvalidIds {
+    radix: u32,
+}
+
+impl InvalidIds {
+    /// Counts invalid IDs in `radix`, which must be within `2..=36`.
+    fn new(radix: u32) -> Self {
+        IdRange::check_radix(radix);
+
+        Self { radix }
+    }
+
+    /// How many invalid IDs are smaller than `n`.
+    fn count_below(&self, n: u128) -> u128 {
+        let radix = self.radix as u128;
+
+        let mut count = 0;
+
+        // Single digit IDs can never be invalid
+        let mut length: u32 = 2;
+        let mut length_start = radix;
+
+        while length_start < n {
+            for pattern_length in 1..length {
+                if !length.is_multiple_of(pattern_length) {
+                    continue;
+                }
+
+                let blocks = self.count_blocks_below(length, pattern_length, n);
+
+                match mobius(length / pattern_length) {
+                    1 => count -= blocks,
+                    -1 => count += blocks,
+                    _ => {}
+                }
+            }
+
+            length += 1;
+
+            // IDs this long no longer fit, so none of them are below n either
+            match length_start.checked_mul(radix) {
+                Some(next_length_start) => length_start = next_length_start,
+                None => break,
+            }
+        }
+
+        count
+    }
+
+    /// How many invalid IDs are smaller than or equal to `n`.
+    fn count_up_to(&self, n: u128) -> u128 {
+        self.count_below(n) + is_invalid_in_radix(n, self.radix) as u128
+    }
+
+    /// The `k`-th smallest invalid ID, counting from zero.
+    fn nth(&self, k: u128) -> Option<u128> {
+        if self.count_up_to(u128::MAX) <= k {
+            return None;
+        }
+
+        Some(self.first_with_count_above(k, 0, u128::MAX))
+    }
+
+    /// The smallest ID within `low..=high` with more than `k` invalid IDs up to and including
+    /// itself. There must be one.
+    fn first_with_count_above(&self, k: u128, mut low: u128, mut high: u128) -> u128 {
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            if self.count_up_to(middle) > k {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+
+        low
+    }
+
+    /// The smallest invalid ID greater than `n`.
+    fn next_after(&self, n: u128) -> Option<u128> {
+        let radix = self.radix as u128;
+
+        // An ID of only the highest digit is invalid from two digits on, so the first one above
+        // n bounds the search much closer than the largest ID
+        let mut high = radix * radix - 1;
+
+        while high <= n {
+            match high
+                .checked_mul(radix)
+                .and_then(|high| high.checked_add(radix - 1))
+            {
+                Some(next_high) => high = next_high,
+                None => return self.nth(self.count_up_to(n)),
+            }
+        }
+
+        Some(self.first_with_count_above(self.count_up_to(n), n + 1, high))
+    }
+
+    /// How many `pattern_length` digit blocks, repeated to `length` digits, are below `n`.
+    fn count_blocks_below(&self, length: u32, pattern_length: u32, n: u128) -> u128 {
+        let radix = self.radix as u128;
+
+        let first_block = radix.pow(pattern_length - 1);
+        let end_block = radix.pow(pattern_length);
+
+        // Summing the powers rather than dividing radix^length - 1, which may not fit
+        let mut multiplier: u128 = 0;
+
+        for i in 0..length / pattern_length {
+            let Some(next_multiplier) = radix
+                .checked_pow(pattern_length * i)
+                .and_then(|power| multiplier.checked_add(power))
+            else {
+                // Not even the smallest block repeated fits, so none are below n
+                return 0;
+            };
+
+            multiplier = next_multiplier;
+        }
+
+        end_block
+            .min(n.div_ceil(multiplier))
+            .saturating_sub(first_block)
+    }
+}
+
+fn mobius(mut n: u32) -> i32 {
+    let mut result = 1;
+
+    let mut factor = 2;
+    while factor * factor <= n {
+        if n.is_multiple_of(factor) {
+            n /= factor;
+
+            if n.is_multiple_of(factor) {
+                return 0;
+            }
+
+            result = -result;
+        }
+
+        factor += 1;
+    }
+
+    if n > 1 {
+        result = -result;
+    }
+
+    result
+}
+
+/// Why an ID is invalid: the smallest pattern that repeats to form it.
+#[derive(PartialEq, Debug)]
+struct InvalidExplanation<'a> {
+    pattern: &'a str,
+    repetitions: usize,
+}
+
+fn explain_invalid(s: &str) -> Option<InvalidExplanation<'_>> {
+    let pattern_length = smallest_pattern_length(s.as_bytes())?;
+
+    let (pattern, _) = s.split_at(pattern_length);
+
+    Some(InvalidExplanation {
+        pattern,
+        repetitions: s.len() / pattern_length,
+    })
+}
+
+/// The length of the smallest pattern repeating at least twice to form `digits`, if any.
+#[allow(clippy::manual_is_multiple_of)]
+fn smallest_pattern_length(digits: &[u8]) -> Option<usize> {
+    let length = digits.len();
+
+    let half_length = length / 2; // We can never have two equal parts where one is longer than the other
+
+    // Trying the shortest patterns first means the first match is the smallest one
+    for pattern_length in 1..=half_length {
+        // We must parts with all equal length
+        if length % pattern_length != 0 {
+            continue;
+        }
+
+        let (pattern, _) = digits.split_at(pattern_length);
+
+        let num_patterns_fit = length / pattern_length;
+
+        let mut ok = false;
+
+        for i_split in 0..num_patterns_fit {
+            let start = i_split * pattern_length;
+            let end = start + pattern_length;
+
+            let part = &digits[start..end];
+
+            // Letter digits of radixes above 10 may be written in either case
+            ok = part.eq_ignore_ascii_case(pattern);
+
+            if !ok {
+                break;
+            }
+        }
+
+        if ok {
+            return Some(pattern_length);
+        }
+    }
+
+    None
+}
+
+#[derive(PartialEq, Debug)]
+struct ExplainedId {
+    id: String,
+    pattern: String,
+    repetitions: usize,
+}
+
+impl ExplainedId {
+    fn explain(id: String) -> Option<Self> {
+        let explanation = explain_invalid(&id)?;
+
+        let pattern = explanation.pattern.to_string();
+        let repetitions = explanation.repetitions;
+
+        Some(Self {
+            id,
+            pattern,
+            repetitions,
+        })
+    }
+}
+
+/// The invalid IDs of each range, together with why they are invalid.
+struct InvalidIdReport<'a> {
+    ranges: Vec<(&'a IdRange<'a>, Vec<ExplainedId>)>,
+}
+
+impl<'a> InvalidIdReport<'a> {
+    fn build(id_ranges: &'a [IdRange<'a>]) -> Self {
+        let ranges = id_ranges
+            .iter()
+            .map(|id_range| (id_range, id_range.iter().explain_invalids()))
+            .collect();
+
+        Self { ranges }
+    }
+}
+
+impl fmt::Display for InvalidIdReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id_range, invalids) in &self.ranges {
+            writeln!(f, "{}: {} invalid", id_range, invalids.len())?;
+
+            for invalid in invalids {
+                writeln!(
+                    f,
+                    "  {} = {} x {}",
+                    invalid.id, invalid.pattern, invalid.repetitions
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How the invalid IDs of some ranges are distributed, to sanity check an ID scheme.
+struct InvalidIdStatistics<'a> {
+    by_digit_length: BTreeMap<usize, u128>,
+    by_pattern_length: BTreeMap<usize, u128>,
+    by_repetitions: BTreeMap<usize, u128>,
+    ranges: Vec<RangeDensity<'a>>,
+}
+
+#[derive(PartialEq, Debug)]
+struct RangeDensity<'a> {
+    id_range: &'a IdRange<'a>,
+    num_invalid: u128,
+    num_total: u128,
+}
+
+impl RangeDensity<'_> {
+    fn density(&self) -> f64 {
+        if self.num_total == 0 {
+            return 0.0;
+        }
+
+        self.num_invalid as f64 / self.num_total as f64
+    }
+}
+
+impl<'a> InvalidIdStatistics<'a> {
+    fn build(id_ranges: &'a [IdRange<'a>]) -> Self {
+        let mut statistics = Self {
+            by_digit_length: BTreeMap::new(),
+            by_pattern_length: BTreeMap::new(),
+            by_repetitions: BTreeMap::new(),
+            ranges: Vec::new(),
+        };
+
+        let mut buffer = [0; MAX_DIGITS];
+
+        for id_range in id_ranges {
+            let width = id_range.width.min_digits();
+
+            let mut num_invalid = 0;
+
+            for id in id_range.iter().numbers() {
+                let digits = radix_digits(id, id_range.radix, width, &mut buffer);
+
+                let Some(pattern_length) = smallest_pattern_length(digits) else {
+                    continue;
+                };
+
+                *statistics.by_digit_length.entry(digits.len()).or_default() += 1;
+                *statistics
+                    .by_pattern_length
+                    .entry(pattern_length)
+                    .or_default() += 1;
+                *statistics
+                    .by_repetitions
+                    .entry(digits.len() / pattern_length)
+                    .or_default() += 1;
+
+                num_invalid += 1;
+            }
+
+            statistics.ranges.push(RangeDensity {
+                id_range,
+                num_invalid,
+                num_total: id_range.iter().remaining(),
+            });
+        }
+
+        statistics
+    }
+}
+
+impl fmt::Display for InvalidIdStatistics<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let breakdowns = [
+            ("digit length", &self.by_digit_length),
+            ("pattern length", &self.by_pattern_length),
+            ("repetitions", &self.by_repetitions),
+        ];
+
+        for (name, breakdown) in breakdowns {
+            writeln!(f, "Invalid IDs by {name}:")?;
+
+            for (key, count) in breakdown {
+                writeln!(f, "  {key}: {count}")?;
+            }
+        }
+
+        writeln!(f, "Invalid density per range:")?;
+
+        for range in &self.ranges {
+            writeln!(
+                f,
+                "  {}: {} of {} ({:.2}%)",
+                range.id_range,
+                range.num_invalid,
+                range.num_total,
+                range.density() * 100.0
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The formats [`export_invalids`] can write.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "json" => Some(ExportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Writes every invalid ID with its range and explanation to `out` as they are found, so the
+/// whole list never has to be held in memory.
+fn export_invalids(
+    id_ranges: &[IdRange],
+    format: ExportFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        ExportFormat::Csv => writeln!(out, "range,id,pattern,repetitions")?,
+        ExportFormat::Json => write!(out, "[")?,
+    }
+
+    let mut buffer = [0; MAX_DIGITS];
+    let mut is_first = true;
+
+    for id_range in id_ranges {
+        let width = id_range.width.min_digits();
+        let range = id_range.to_string();
+
+        for id in id_range.iter().numbers() {
+            let digits = radix_digits(id, id_range.radix, width, &mut buffer);
+
+            let Some(pattern_length) = smallest_pattern_length(digits) else {
+                continue;
+            };
+
+            // Digits are always ASCII
+            let digits_str = std::str::from_utf8(digits).unwrap();
+            let pattern = &digits_str[..pattern_length];
+            let repetitions = digits.len() / pattern_length;
+
+            match format {
+                ExportFormat::Csv => writeln!(
+                    out,
+                    "{},{digits_str},{pattern},{repetitions}",
+                    csv_field(&range)
+                )?,
+                ExportFormat::Json => {
+                    let separator = if is_first { "" } else { "," };
+
+                    write!(
+                        out,
+                        "{separator}\n  {{\"range\":{},\"id\":\"{digits_str}\",\"pattern\":\"{pattern}\",\"repetitions\":{repetitions}}}",
+                        json_string(&range)
+                    )?
+                }
+            }
+
+            is_first = false;
+        }
+    }
+
+    match format {
+        ExportFormat::Csv => Ok(()),
+        ExportFormat::Json if is_first => writeln!(out, "]"),
+        ExportFormat::Json => writeln!(out, "\n]"),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if !value.contains([',', '"', '\n', '\r']) {
+        return value.to_string();
+    }
+
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use crate::{
+        ExplainedId, ExportFormat, IdRange, IdRangeIterator, IdWidth, InvalidExplanation,
+        InvalidIdReport, InvalidIdStatistics, InvalidIds, NearestValidIds, RangeDensity,
+        SAMPLE_INPUT, comma_splitter, csv_field, explain_invalid, export_invalids, is_invalid,
+        is_invalid_in_radix, is_invalid_padded, json_string, parallel_sum_invalids, sum_invalids,
+        sum_invalids_via_strings, to_padded_radix_string, to_radix_string,
+    };
+
+    const SAMPLE_INPUT_SINGLE: &str = "11-22";
+    const SAMPLE_INPUT_SHORT: &str = "11-22,95-115,998-1012";
+
+    #[test]
+    fn test_id_range_parses_correctly() {
+        let id_range = IdRange::parse(SAMPLE_INPUT_SINGLE);
+
+        assert_eq!(
+            id_range,
+            IdRange {
+                start: "11",
+                end: "22",
+                radix: 10,
+                width: IdWidth::Natural
+            }
+        );
+    }
+
+    #[test]
+    fn test_comma_splitter() {
+        let result = comma_splitter(SAMPLE_INPUT_SHORT);
+
+        assert_eq!(result, vec!["11-22", "95-115", "998-1012"]);
+    }
+
+    #[test]
+    fn test_parse_multiple() {
+        let id_ranges = IdRange::parse_multiple(SAMPLE_INPUT_SHORT);
+
+        assert_eq!(
+            id_ranges,
+            vec![
+                IdRange {
+                    start: "11",
+                    end: "22",
+                    radix: 10,
+                    width: IdWidth::Natural
+                },
+                IdRange {
+                    start: "95",
+                    end: "115",
+                    radix: 10,
+                    width: IdWidth::Natural
+                },
+                IdRange {
+                    start: "998",
+                    end: "1012",
+                    radix: 10,
+                    width: IdWidth::Natural
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::bool_assert_comparison)]
+    fn test_is_invalid() {
+        assert_eq!(is_invalid("101"), false);
+        assert_eq!(is_invalid("1234"), false);
+        assert_eq!(is_invalid("38593862"), false);
+
+        assert_eq!(is_invalid("11"), true);
+        assert_eq!(is_invalid("22"), true);
+        assert_eq!(is_invalid("1188511885"), true);
+        assert_eq!(is_invalid("446446"), true);
+        assert_eq!(is_invalid("38593859"), true);
+
+        assert_eq!(is_invalid("12341234"), true);
+        assert_eq!(is_invalid("123123123"), true);
+        assert_eq!(is_invalid("1212121212"), true);
+        assert_eq!(is_invalid("1111111"), true);
+    }
+
+    #[test]
+    fn test_id_range_iterators() {
+        let id_range = IdRange {
+            start: "9",
+            end: "12",
+            radix: 10,
+            width: IdWidth::Natural,
+        };
+
+        assert_eq!(
+            id_range.iter().collect::<Vec<String>>(),
+            vec![
+                String::from("9"),
+                String::from("10"),
+                String::from("11"),
+                String::from("12")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_id_range_iterator_invalids() {
+        assert_eq!(
+            IdRange::parse("11-22").iter().find_invalids(),
+            vec![String::from("11"), String::from("22")]
+        );
+        assert_eq!(
+            IdRange::parse("95-115").iter().find_invalids(),
+            vec![String::from("99"), String::from("111"),]
+        );
+        assert_eq!(
+            IdRange::parse("998-1012").iter().find_invalids(),
+            vec![String::from("999"), String::from("1010"),]
+        );
+        assert_eq!(
+            IdRange::parse("1188511880-1188511890")
+                .iter()
+                .find_invalids(),
+            vec![String::from("1188511885"),]
+        );
+        assert_eq!(
+            IdRange::parse("222220-222224").iter().find_invalids(),
+            vec![String::from("222222"),]
+        );
+        assert_eq!(
+            IdRange::parse("1698522-1698528").iter().find_invalids(),
+            Vec::<String>::new(),
+        );
+        assert_eq!(
+            IdRange::parse("446443-446449").iter().find_invalids(),
+            vec![String::from("446446"),]
+        );
+        assert_eq!(
+            IdRange::parse("38593856-38593862").iter().find_invalids(),
+            vec![String::from("38593859"),]
+        );
+        assert_eq!(
+            IdRange::parse("565653-565659").iter().find_invalids(),
+            vec![String::from("565656"),]
+        );
+        assert_eq!(
+            IdRange::parse("824824821-824824827").iter().find_invalids(),
+            vec![String::from("824824824"),]
+        );
+        assert_eq!(
+            IdRange::parse("2121212118-2121212124")
+                .iter()
+                .find_invalids(),
+            vec![String::from("2121212121"),]
+        );
+    }
+
+    #[test]
+    fn test_explain_invalid() {
+        assert_eq!(explain_invalid("101"), None);
+        assert_eq!(explain_invalid("38593862"), None);
+
+        assert_eq!(
+            explain_invalid("121212"),
+            Some(InvalidExplanation {
+                pattern: "12",
+                repetitions: 3
+            })
+        );
+        assert_eq!(
+            explain_invalid("1111111"),
+            Some(InvalidExplanation {
+                pattern: "1",
+                repetitions: 7
+            })
+        );
+        assert_eq!(
+            explain_invalid("12341234"),
+            Some(InvalidExplanation {
+                pattern: "1234",
+                repetitions: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_id_range_iterator_explain_invalids() {
+        assert_eq!(
+            IdRange::parse("95-115").iter().explain_invalids(),
+            vec![
+                ExplainedId {
+                    id: String::from("99"),
+                    pattern: String::from("9"),
+                    repetitions: 2
+                },
+                ExplainedId {
+                    id: String::from("111"),
+                    pattern: String::from("1"),
+                    repetitions: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_id_report() {
+        let id_ranges = IdRange::parse_multiple("95-115,1698522-1698528,998-1012");
+
+        assert_eq!(
+            InvalidIdReport::build(&id_ranges).to_string(),
+            "95-115: 2 invalid
+  99 = 9 x 2
+  111 = 1 x 3
+1698522-1698528: 0 invalid
+998-1012: 2 invalid
+  999 = 9 x 3
+  1010 = 10 x 2
+"
+        );
+    }
+
+    #[test]
+    fn test_to_radix_string() {
+        assert_eq!(to_radix_string(0, 10), "0");
+        assert_eq!(to_radix_string(1012, 10), "1012");
+        assert_eq!(to_radix_string(0xabab, 16), "abab");
+        assert_eq!(to_radix_string(10, 2), "1010");
+        assert_eq!(to_radix_string(36 * 36 - 1, 36), "zz");
+    }
+
+    #[test]
+    fn test_is_invalid_in_radix() {
+        assert!(is_invalid_in_radix(0xabab, 16));
+        assert!(is_invalid_in_radix(0xfff, 16));
+        assert!(is_invalid_in_radix(10, 2));
+        assert!(is_invalid_in_radix(36 * 36 - 1, 36));
+
+        assert!(!is_invalid_in_radix(0xabc, 16));
+        assert!(!is_invalid_in_radix(11, 2));
+        assert!(!is_invalid_in_radix(11, 16));
+
+        assert!(is_invalid("AbaB"));
+    }
+
+    #[test]
+    fn test_id_range_radix_parses_correctly() {
+        assert_eq!(
+            IdRange::parse_multiple_with_radix("a0-c0,f-10", 16),
+            vec![
+                IdRange {
+                    start: "a0",
+                    end: "c0",
+                    radix: 16,
+                    width: IdWidth::Natural
+                },
+                IdRange {
+                    start: "f",
+                    end: "10",
+                    radix: 16,
+                    width: IdWidth::Natural
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_id_range_radix_iterators() {
+        assert_eq!(
+            IdRange::parse_with_radix("fe-101", 16)
+                .iter()
+                .collect::<Vec<String>>(),
+            vec![
+                String::from("fe"),
+                String::from("ff"),
+                String::from("100"),
+                String::from("101")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_id_range_radix_iterator_invalids() {
+        assert_eq!(
+            IdRange::parse_with_radix("a0-C0", 16)
+                .iter()
+                .find_invalids(),
+            vec![String::from("aa"), String::from("bb")]
+        );
+        assert_eq!(
+            IdRange::parse_with_radix("zx-101", 36)
+                .iter()
+                .find_invalids(),
+            vec![String::from("zz")]
+        );
+        assert_eq!(
+            IdRange::parse_with_radix("101-1011", 2)
+                .iter()
+                .find_invalids(),
+            vec![String::from("111"), String::from("1010")]
+        );
+    }
+
+    #[test]
+    fn test_id_range_count_and_sum_invalids() {
+        let id_ranges = IdRange::parse_multiple(SAMPLE_INPUT);
+
+        assert_eq!(
+            id_ranges
+                .iter()
+                .map(IdRange::count_invalids)
+                .collect::<Vec<u128>>(),
+            vec![2, 2, 2, 1, 1, 0, 1, 1, 1, 1, 1]
+        );
+        assert_eq!(
+            sum_invalids(&id_ranges),
+            Some(sum_invalids_via_strings(&id_ranges))
+        );
+        assert_eq!(
+            IdRange::parse_with_radix("a0-C0", 16).sum_invalids(),
+            Some(0xaa + 0xbb)
+        );
+    }
 
-    println!("The sum is: {}", sum_of_invalids);
-}
+    #[test]
+    fn test_id_range_iterator_traits() {
+        let id_range = IdRange::parse("9-12");
 
-#[derive(PartialEq, Debug)]
-struct IdRange<'a> {
-    start: &'a str,
-    end: &'a str,
-}
+        assert_eq!(id_range.iter().remaining(), 4);
+        assert_eq!(id_range.iter().count(), 4);
+        assert_eq!(id_range.iter().size_hint(), (4, Some(4)));
+        assert_eq!(id_range.iter().last(), Some(String::from("12")));
+        assert_eq!(
+            id_range.iter().rev().collect::<Vec<String>>(),
+            vec![
+                String::from("12"),
+                String::from("11"),
+                String::from("10"),
+                String::from("9")
+            ]
+        );
 
-struct IdRangeIterator<'a> {
-    end: &'a str,
-    current: usize,
-}
+        let mut iter = id_range.iter();
+        assert_eq!(iter.nth(1), Some(String::from("10")));
+        assert_eq!(iter.nth_back(0), Some(String::from("12")));
+        assert_eq!(iter.remaining(), 1);
+        assert_eq!(iter.next_back(), Some(String::from("11")));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.remaining(), 0);
 
-impl<'a> Iterator for IdRangeIterator<'a> {
-    type Item = String;
+        let mut iter = id_range.iter();
+        assert_eq!(iter.nth(4), None);
+        assert_eq!(iter.next(), None);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let end: usize = self.end.parse().unwrap();
+        assert_eq!(IdRange::parse("12-9").iter().remaining(), 0);
 
-        if self.current > end {
-            return None;
-        }
+        let iter = IdRangeIterator::<u128>::new(0, u128::MAX, 10, 1);
+        assert_eq!(iter.remaining(), u128::MAX);
+        assert_eq!(iter.size_hint(), (usize::MAX, None));
+    }
 
-        let value = self.current.to_string();
+    #[test]
+    fn test_id_range_iterator_numbers() {
+        assert_eq!(
+            IdRange::parse_with_radix("fe-101", 16)
+                .iter()
+                .numbers()
+                .collect::<Vec<u128>>(),
+            vec![0xfe, 0xff, 0x100, 0x101]
+        );
 
-        self.current += 1;
+        let mut iter = IdRangeIterator::<u128>::new(u128::MAX - 2, u128::MAX, 10, 1);
+        assert_eq!(iter.nth(2), Some(u128::MAX));
+        assert_eq!(iter.next(), None);
 
-        Some(value)
+        let mut iter = IdRangeIterator::<u128>::new(0, u128::MAX, 10, 1);
+        assert_eq!(iter.size_hint(), (usize::MAX, None));
+        assert_eq!(iter.next_back(), Some(u128::MAX));
     }
-}
 
-impl<'a> IdRangeIterator<'a> {
-    fn find_invalids(self) -> Vec<String> {
-        let mut invalids: Vec<String> = Vec::new();
+    #[test]
+    fn test_invalid_ids_count_below() {
+        for radix in [2, 10, 16] {
+            let invalid_ids = InvalidIds::new(radix);
 
-        for id in self.collect::<Vec<String>>() {
-            if is_invalid(&id) {
-                invalids.push(id);
+            let mut num_below = 0;
+            for n in 0..5000 {
+                assert_eq!(
+                    invalid_ids.count_below(n),
+                    num_below,
+                    "{n} in radix {radix}"
+                );
+
+                if is_invalid_in_radix(n, radix) {
+                    num_below += 1;
+                }
             }
         }
 
-        invalids
+        assert_eq!(InvalidIds::new(10).count_below(100), 9);
+        assert_eq!(InvalidIds::new(10).count_below(1000), 9 + 9);
+        assert_eq!(InvalidIds::new(10).count_below(10000), 9 + 9 + 90);
     }
-}
 
-impl<'a> IdRange<'a> {
-    fn parse(input: &'a str) -> Self {
-        let split: Vec<&str> = input.split("-").collect();
-
-        let start = split[0];
-        let end = split[1];
-
-        Self { start, end }
-    }
+    #[test]
+    fn test_invalid_ids_nth() {
+        let invalid_ids = InvalidIds::new(10);
 
-    fn iter(&self) -> IdRangeIterator<'a> {
-        let current: usize = self.start.parse().unwrap();
+        assert_eq!(invalid_ids.nth(0), Some(11));
+        assert_eq!(invalid_ids.nth(8), Some(99));
+        assert_eq!(invalid_ids.nth(9), Some(111));
+        assert_eq!(invalid_ids.nth(18), Some(1010));
 
-        IdRangeIterator {
-            end: self.end,
-            current,
+        let brute_force = (0..5000)
+            .filter(|id| is_invalid_in_radix(*id, 16))
+            .collect::<Vec<u128>>();
+        for (k, id) in brute_force.iter().enumerate() {
+            assert_eq!(InvalidIds::new(16).nth(k as u128), Some(*id));
         }
+
+        assert_eq!(invalid_ids.nth(u128::MAX), None);
+        assert_eq!(
+            InvalidIds::new(2).nth(InvalidIds::new(2).count_below(u128::MAX)),
+            Some(u128::MAX)
+        );
     }
 
-    fn parse_multiple(comma_separated_input: &'a str) -> Vec<Self> {
-        let splitted = comma_splitter(comma_separated_input);
+    #[test]
+    fn test_invalid_ids_next_after() {
+        let invalid_ids = InvalidIds::new(10);
+
+        assert_eq!(invalid_ids.next_after(0), Some(11));
+        assert_eq!(invalid_ids.next_after(11), Some(22));
+        assert_eq!(invalid_ids.next_after(99), Some(111));
+        assert_eq!(invalid_ids.next_after(1188511880), Some(1188511885));
+        assert_eq!(invalid_ids.next_after(2121212118), Some(2121212121));
 
-        splitted.iter().map(|s| IdRange::parse(s)).collect()
+        assert_eq!(
+            InvalidIds::new(2).next_after(u128::MAX - 1),
+            Some(u128::MAX)
+        );
+        assert_eq!(InvalidIds::new(2).next_after(u128::MAX), None);
     }
-}
 
-fn comma_splitter(sample_input_short: &str) -> Vec<&str> {
-    sample_input_short.split(",").collect()
-}
+    #[test]
+    fn test_to_padded_radix_string() {
+        assert_eq!(to_padded_radix_string(7, 10, 3), "007");
+        assert_eq!(to_padded_radix_string(1012, 10, 3), "1012");
+        assert_eq!(to_padded_radix_string(0, 16, 4), "0000");
+    }
 
-fn is_invalid(s: &str) -> bool {
-    let length = s.len();
+    #[test]
+    fn test_is_invalid_padded() {
+        assert!(is_invalid_padded(101, 10, 4));
+        assert!(is_invalid_padded(0, 10, 2));
+        assert!(!is_invalid_padded(7, 10, 2));
 
-    let half_length = length / 2; // We can never have two equal parts where one is longer than the other
+        assert!(!is_invalid_in_radix(101, 10));
+        assert!(!is_invalid_padded(101, 10, 3));
+        assert!(!is_invalid_padded(0, 10, 1));
+    }
 
-    for pattern_length in 1..=half_length {
-        // We must parts with all equal length
-        if length % pattern_length != 0 {
-            continue;
-        }
+    #[test]
+    fn test_id_range_fixed_width() {
+        let id_range = IdRange::parse("007-012").fixed_width();
 
-        let (pattern, _) = s.split_at(pattern_length);
+        assert_eq!(id_range.width, IdWidth::Fixed(3));
+        assert_eq!(
+            id_range.iter().collect::<Vec<String>>(),
+            vec!["007", "008", "009", "010", "011", "012"]
+        );
 
-        let num_patterns_fit = s.len() / pattern_length;
+        assert_eq!(
+            IdRange::parse("0100-0102")
+                .fixed_width()
+                .iter()
+                .find_invalids(),
+            vec![String::from("0101")]
+        );
+        assert_eq!(
+            IdRange::parse("0100-0102").iter().find_invalids(),
+            Vec::<String>::new()
+        );
 
-        let mut ok = false;
+        let id_range = IdRange::parse("00-11").fixed_width();
+        assert_eq!(id_range.iter().find_invalids(), vec!["00", "11"]);
+        assert_eq!(id_range.count_invalids(), 2);
+        assert_eq!(id_range.sum_invalids(), Some(11));
+    }
 
-        for i_split in 0..num_patterns_fit {
-            let start = i_split * pattern_length;
-            let end = start + pattern_length;
+    #[test]
+    fn test_invalid_id_statistics() {
+        let id_ranges = IdRange::parse_multiple("95-115,998-1012,1698522-1698528");
 
-            let part = &s[start..end];
+        let statistics = InvalidIdStatistics::build(&id_ranges);
 
-            ok = part == pattern;
+        assert_eq!(
+            statistics.by_digit_length.into_iter().collect::<Vec<_>>(),
+            vec![(2, 1), (3, 2), (4, 1)]
+        );
+        assert_eq!(
+            statistics.by_pattern_length.into_iter().collect::<Vec<_>>(),
+            vec![(1, 3), (2, 1)]
+        );
+        assert_eq!(
+            statistics.by_repetitions.into_iter().collect::<Vec<_>>(),
+            vec![(2, 2), (3, 2)]
+        );
+        assert_eq!(
+            statistics.ranges,
+            vec![
+                RangeDensity {
+                    id_range: &id_ranges[0],
+                    num_invalid: 2,
+                    num_total: 21
+                },
+                RangeDensity {
+                    id_range: &id_ranges[1],
+                    num_invalid: 2,
+                    num_total: 15
+                },
+                RangeDensity {
+                    id_range: &id_ranges[2],
+                    num_invalid: 0,
+                    num_total: 7
+                },
+            ]
+        );
+    }
 
-            if !ok {
-                break;
-            }
-        }
+    #[test]
+    fn test_invalid_id_statistics_display() {
+        let id_ranges = IdRange::parse_multiple("11-22,1698522-1698528");
 
-        if ok {
-            return true;
-        }
+        assert_eq!(
+            InvalidIdStatistics::build(&id_ranges).to_string(),
+            "Invalid IDs by digit length:
+  2: 2
+Invalid IDs by pattern length:
+  1: 2
+Invalid IDs by repetitions:
+  2: 2
+Invalid density per range:
+  11-22: 2 of 12 (16.67%)
+  1698522-1698528: 0 of 7 (0.00%)
+"
+        );
     }
 
-    false
-}
-
-#[cfg(test)]
-mod tests {
-    use std::vec;
+    #[test]
+    fn test_export_invalids_csv() {
+        let id_ranges = IdRange::parse_multiple("95-115,1698522-1698528,998-1012");
 
-    use crate::{IdRange, comma_splitter, is_invalid};
+        let mut out = Vec::new();
+        export_invalids(&id_ranges, ExportFormat::Csv, &mut out).unwrap();
 
-    const SAMPLE_INPUT_SINGLE: &str = "11-22";
-    const SAMPLE_INPUT_SHORT: &str = "11-22,95-115,998-1012";
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "range,id,pattern,repetitions
+95-115,99,9,2
+95-115,111,1,3
+998-1012,999,9,3
+998-1012,1010,10,2
+"
+        );
+    }
 
     #[test]
-    fn test_id_range_parses_correctly() {
-        let id_range = IdRange::parse(SAMPLE_INPUT_SINGLE);
+    fn test_export_invalids_json() {
+        let id_ranges = vec![
+            IdRange::parse("11-22"),
+            IdRange::parse_with_radix("a0-c0", 16),
+        ];
+
+        let mut out = Vec::new();
+        export_invalids(&id_ranges, ExportFormat::Json, &mut out).unwrap();
 
         assert_eq!(
-            id_range,
-            IdRange {
-                start: "11",
-                end: "22"
-            }
+            String::from_utf8(out).unwrap(),
+            r#"[
+  {"range":"11-22","id":"11","pattern":"1","repetitions":2},
+  {"range":"11-22","id":"22","pattern":"2","repetitions":2},
+  {"range":"a0-c0","id":"aa","pattern":"a","repetitions":2},
+  {"range":"a0-c0","id":"bb","pattern":"b","repetitions":2}
+]
+"#
         );
+
+        let mut out = Vec::new();
+        export_invalids(
+            &IdRange::parse_multiple("1698522-1698528"),
+            ExportFormat::Json,
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "[]\n");
     }
 
     #[test]
-    fn test_comma_splitter() {
-        let result = comma_splitter(SAMPLE_INPUT_SHORT);
+    fn test_export_escaping() {
+        assert_eq!(csv_field("11-22"), "11-22");
+        assert_eq!(csv_field("1,1"), "\"1,1\"");
+        assert_eq!(csv_field("1\"1"), "\"1\"\"1\"");
 
-        assert_eq!(result, vec!["11-22", "95-115", "998-1012"]);
+        assert_eq!(json_string("11-22"), "\"11-22\"");
+        assert_eq!(json_string("1\"\\1\n"), "\"1\\\"\\\\1\\u000a\"");
     }
 
     #[test]
-    fn test_parse_multiple() {
-        let id_ranges = IdRange::parse_multiple(SAMPLE_INPUT_SHORT);
+    fn test_id_range_iterator_chunks() {
+        let chunks = IdRange::parse("9-20").iter().numbers().chunks(5);
 
         assert_eq!(
-            id_ranges,
+            chunks
+                .into_iter()
+                .map(|chunk| chunk.collect::<Vec<u128>>())
+                .collect::<Vec<_>>(),
             vec![
-                IdRange {
-                    start: "11",
-                    end: "22"
-                },
-                IdRange {
-                    start: "95",
-                    end: "115"
-                },
-                IdRange {
-                    start: "998",
-                    end: "1012"
-                },
+                vec![9, 10, 11, 12, 13],
+                vec![14, 15, 16, 17, 18],
+                vec![19, 20]
             ]
         );
+
+        let chunks = IdRangeIterator::<u128>::new(u128::MAX - 2, u128::MAX, 10, 1).chunks(2);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].clone().collect::<Vec<u128>>(), vec![u128::MAX]);
+
+        assert!(IdRange::parse("20-9").iter().numbers().chunks(5).is_empty());
     }
 
     #[test]
-    fn test_is_invalid() {
-        assert_eq!(is_invalid("101"), false);
-        assert_eq!(is_invalid("1234"), false);
-        assert_eq!(is_invalid("38593862"), false);
+    fn test_parallel_sum_invalids() {
+        let id_ranges = IdRange::parse_multiple(SAMPLE_INPUT);
 
-        assert_eq!(is_invalid("11"), true);
-        assert_eq!(is_invalid("22"), true);
-        assert_eq!(is_invalid("1188511885"), true);
-        assert_eq!(is_invalid("446446"), true);
-        assert_eq!(is_invalid("38593859"), true);
+        for num_threads in [0, 1, 2, 3, 8] {
+            assert_eq!(
+                parallel_sum_invalids(&id_ranges, num_threads),
+                Some(4174379265),
+                "{num_threads} threads"
+            );
+        }
 
-        assert_eq!(is_invalid("12341234"), true);
-        assert_eq!(is_invalid("123123123"), true);
-        assert_eq!(is_invalid("1212121212"), true);
-        assert_eq!(is_invalid("1111111"), true);
-    }
+        let id_ranges = IdRange::parse_multiple("1-200000,500-600,1000000-1050000");
+        assert_eq!(
+            parallel_sum_invalids(&id_ranges, 4),
+            sum_invalids(&id_ranges)
+        );
 
-    #[test]
-    fn test_id_range_iterators() {
-        let id_range = IdRange {
-            start: "9",
-            end: "12",
-        };
+        // More IDs than fit a u128, whose invalid IDs overflow the sum
+        let id_ranges = IdRange::parse_multiple("0-340282366920938463463374607431768211455,0-5");
+        for num_threads in [1, 4] {
+            assert_eq!(parallel_sum_invalids(&id_ranges, num_threads), None);
+        }
 
+        let id_ranges = IdRange::parse_multiple_with_radix("0-fffff,8-ff", 16);
         assert_eq!(
-            id_range.iter().collect::<Vec<String>>(),
-            vec![
-                String::from("9"),
-                String::from("10"),
-                String::from("11"),
-                String::from("12")
-            ]
+            parallel_sum_invalids(&id_ranges, 3),
+            sum_invalids(&id_ranges)
         );
     }
 
     #[test]
-    fn test_id_range_iterator_invalids() {
+    fn test_ids_around_u64_max() {
+        let last_invalid_u64 = 18446744071844674407;
+        let first_invalid_past_u64 = 18446744081844674408;
+
+        assert!(last_invalid_u64 <= u64::MAX as u128);
+        assert!(first_invalid_past_u64 > u64::MAX as u128);
+
+        assert!(is_invalid_in_radix(last_invalid_u64, 10));
+        assert!(is_invalid_in_radix(first_invalid_past_u64, 10));
+        assert!(!is_invalid_in_radix(u64::MAX as u128, 10));
+        assert!(is_invalid_in_radix(u64::MAX as u128, 2));
+        assert!(is_invalid_in_radix(u128::MAX, 2));
+
+        let id_ranges = IdRange::parse_multiple(
+            "18446744071844674400-18446744071844674410,18446744081844674400-18446744081844674410",
+        );
         assert_eq!(
-            IdRange::parse("11-22").iter().find_invalids(),
-            vec![String::from("11"), String::from("22")]
+            id_ranges
+                .iter()
+                .flat_map(|id_range| id_range.iter().find_invalids())
+                .collect::<Vec<String>>(),
+            vec!["18446744071844674407", "18446744081844674408"]
         );
         assert_eq!(
-            IdRange::parse("95-115").iter().find_invalids(),
-            vec![String::from("99"), String::from("111"),]
+            sum_invalids(&id_ranges),
+            Some(last_invalid_u64 + first_invalid_past_u64)
         );
         assert_eq!(
-            IdRange::parse("998-1012").iter().find_invalids(),
-            vec![String::from("999"), String::from("1010"),]
+            parallel_sum_invalids(&id_ranges, 2),
+            Some(last_invalid_u64 + first_invalid_past_u64)
         );
+
+        let invalid_ids = InvalidIds::new(10);
         assert_eq!(
-            IdRange::parse("1188511880-1188511890")
-                .iter()
-                .find_invalids(),
-            vec![String::from("1188511885"),]
+            invalid_ids.next_after(u64::MAX as u128),
+            Some(first_invalid_past_u64)
         );
         assert_eq!(
-            IdRange::parse("222220-222224").iter().find_invalids(),
-            vec![String::from("222222"),]
+            invalid_ids.nth(invalid_ids.count_below(u64::MAX as u128) - 1),
+            Some(last_invalid_u64)
+        );
+    }
+
+    #[test]
+    fn test_sum_invalids_overflow() {
+        let largest_invalid = 340282366920934028236692093402823669209;
+
+        assert!(is_invalid_in_radix(largest_invalid, 10));
+        assert_eq!(InvalidIds::new(10).next_after(largest_invalid), None);
+
+        let id_range = IdRange::parse(
+            "340282366920934028236692093402823669200-340282366920934028236692093402823669210",
         );
+        assert_eq!(id_range.sum_invalids(), Some(largest_invalid));
+
+        let id_ranges = vec![
+            id_range,
+            IdRange::parse(
+                "340282366920934028236692093402823669209-340282366920934028236692093402823669209",
+            ),
+        ];
+        assert_eq!(sum_invalids(&id_ranges), None);
+        assert_eq!(parallel_sum_invalids(&id_ranges, 2), None);
+    }
+
+    #[test]
+    fn test_id_range_nearest_valid() {
+        let id_range = IdRange::parse("95-115");
+
         assert_eq!(
-            IdRange::parse("1698522-1698528").iter().find_invalids(),
-            Vec::<String>::new(),
+            id_range.nearest_valid(99),
+            NearestValidIds {
+                below: Some(98),
+                above: Some(100)
+            }
         );
         assert_eq!(
-            IdRange::parse("446443-446449").iter().find_invalids(),
-            vec![String::from("446446"),]
+            id_range.nearest_valid(111),
+            NearestValidIds {
+                below: Some(110),
+                above: Some(112)
+            }
         );
         assert_eq!(
-            IdRange::parse("38593856-38593862").iter().find_invalids(),
-            vec![String::from("38593859"),]
+            id_range.nearest_valid(200),
+            NearestValidIds {
+                below: Some(115),
+                above: None
+            }
         );
         assert_eq!(
-            IdRange::parse("565653-565659").iter().find_invalids(),
-            vec![String::from("565656"),]
+            id_range.nearest_valid(10),
+            NearestValidIds {
+                below: None,
+                above: Some(95)
+            }
         );
+
+        let id_range = IdRange::parse("11-22");
         assert_eq!(
-            IdRange::parse("824824821-824824827").iter().find_invalids(),
-            vec![String::from("824824824"),]
+            id_range.nearest_valid(11),
+            NearestValidIds {
+                below: None,
+                above: Some(12)
+            }
         );
         assert_eq!(
-            IdRange::parse("2121212118-2121212124")
-                .iter()
-                .find_invalids(),
-            vec![String::from("2121212121"),]
+            id_range.nearest_valid(22),
+            NearestValidIds {
+                below: Some(21),
+                above: None
+            }
+        );
+
+        assert_eq!(
+            IdRange::parse("00-11").fixed_width().nearest_valid(0),
+            NearestValidIds {
+                below: None,
+                above: Some(1)
+            }
+        );
+        assert_eq!(
+            IdRange::parse_with_radix("a0-c0", 16).nearest_valid(0xbb),
+            NearestValidIds {
+                below: Some(0xba),
+                above: Some(0xbc)
+            }
+        );
+        assert_eq!(
+            IdRange::parse_with_radix("10-11", 2).nearest_valid(3),
+            NearestValidIds {
+                below: Some(2),
+                above: None
+            }
         );
     }
+
+    #[test]
+    #[should_panic(expected = "The radix must be between 2 and 36")]
+    fn test_id_range_rejects_radix_above_36() {
+        IdRange::parse_with_radix("1-2", 37);
+    }
+
+    #[test]
+    #[should_panic(expected = "\"ZY\" is not an ID in radix 16")]
+    fn test_id_range_rejects_digits_outside_radix() {
+        IdRange::parse_with_radix("ZY-10", 16);
+    }
+
+    #[test]
+    #[should_panic(expected = "The radix must be between 2 and 36")]
+    fn test_invalid_ids_rejects_radix_below_2() {
+        InvalidIds::new(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "More IDs remain than fit a usize")]
+    fn test_id_range_iterator_count_overflow() {
+        IdRangeIterator::<u128>::new(0, u128::MAX, 10, 1).count();
+    }
 }
@@ -1,129 +1,3239 @@
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    fs::File,
+    hint,
+    io::{self, BufRead, BufReader, Read},
+    iter::{self, Sum},
+    ops::{Add, AddAssign, RangeInclusive},
+    process,
+    str::FromStr,
+    time::Instant,
+};
+
+/// How many batteries each part of the puzzle uses.
+const PARTS: [usize; 2] = [2, 12];
+
 fn main() {
     const INPUT: &str = include_str!("input.txt");
 
-    let battery_banks = BatteryBank::parse_multiple(INPUT);
+    let args: Vec<String> = std::env::args().collect();
+
+    let radix = flag_value(&args, "--radix", "a number from 2 to 36", |n| {
+        n.parse::<u32>()
+            .ok()
+            .filter(|radix| BatteryBank::RADIXES.contains(radix))
+    })
+    .unwrap_or(BatteryBank::DEFAULT_RADIX);
+
+    let input_path = flag_value(&args, "--input", "a path, or - for standard input", Some);
+
+    let options = if args.iter().any(|arg| arg == "--normalize") {
+        ParseOptions {
+            strip_carriage_return: true,
+            trim_whitespace: true,
+        }
+    } else {
+        ParseOptions::default()
+    };
+
+    // Only the sum streams the banks, the other modes need all of them at once
+    let read_battery_banks = || {
+        let battery_banks = match input_path {
+            Some(path) => BatteryBank::read_multiple(open_input(path), radix, options)
+                .collect::<io::Result<Vec<BatteryBank>>>()
+                .map_err(|error| error.to_string()),
+            None => BatteryBank::try_parse_multiple_with_radix(INPUT, radix, options)
+                .map_err(|error| error.to_string()),
+        };
+
+        battery_banks.unwrap_or_else(|error| exit_unreadable(&error))
+    };
+
+    let policy = flag_value(
+        &args,
+        "--short-banks",
+        "skip, error or use-all",
+        ShortBankPolicy::parse,
+    )
+    .unwrap_or(ShortBankPolicy::Error);
+
+    // Every requested number of batteries, labelled by the part it answers if any
+    let mut requested: Vec<(String, usize)> = Vec::new();
+
+    for (i, arg) in args.iter().enumerate() {
+        match arg.as_str() {
+            "--part" => {
+                let part = value_after(&args, i, "1 or 2", |part| {
+                    part.parse::<usize>()
+                        .ok()
+                        .filter(|part| (1..=PARTS.len()).contains(part))
+                });
+
+                requested.push((format!("Part {part}"), PARTS[part - 1]));
+            }
+            "--batteries" => {
+                let num_batteries_to_use =
+                    value_after(&args, i, "a number", |n| n.parse::<usize>().ok());
+
+                requested.push((
+                    format!("{num_batteries_to_use} batteries"),
+                    num_batteries_to_use,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    if requested.is_empty() {
+        requested = (1..=PARTS.len())
+            .map(|part| (format!("Part {part}"), PARTS[part - 1]))
+            .collect();
+    }
+
+    match args.get(1).map(String::as_str) {
+        Some("bench") => {
+            bench();
+            return;
+        }
+        Some("audit") => {
+            // Wear counts pick the least worn batteries, and apply to every bank alike
+            let wear = flag_value(&args, "--wear", "comma separated wear counts", |wear| {
+                wear.split(',').map(|count| count.parse().ok()).collect()
+            });
+
+            let tie_break = match wear {
+                Some(wear) => TieBreak::LeastWorn(wear),
+                None => flag_value(&args, "--tie-break", "earliest or latest", TieBreak::parse)
+                    .unwrap_or(TieBreak::Earliest),
+            };
+
+            for battery_bank in &read_battery_banks() {
+                for (label, num_batteries_to_use) in &requested {
+                    let selection = battery_bank
+                        .select_highest_voltage_with_tie_break(*num_batteries_to_use, &tie_break);
+
+                    print_selection(battery_bank, selection, label, radix);
+                }
+            }
+            return;
+        }
+        Some("select") => {
+            let objective = flag_value(
+                &args,
+                "--objective",
+                "highest, lowest, lowest-with-zero, at-most:N or divisible-by:N",
+                VoltageObjective::parse,
+            )
+            .unwrap_or(VoltageObjective::Highest);
+
+            let constraint = flag_value(
+                &args,
+                "--constraint",
+                "contiguous, max-gap:N or min-spacing:N",
+                SelectionConstraint::parse,
+            );
+
+            assert!(
+                constraint.is_none() || objective == VoltageObjective::Highest,
+                "--constraint only applies to the highest objective"
+            );
+
+            for battery_bank in &read_battery_banks() {
+                for (label, num_batteries_to_use) in &requested {
+                    let selection = match &constraint {
+                        Some(constraint) => battery_bank.select_highest_voltage_with_constraint(
+                            *num_batteries_to_use,
+                            constraint,
+                        ),
+                        None => battery_bank.select_voltage(*num_batteries_to_use, &objective),
+                    };
+
+                    print_selection(battery_bank, selection, label, radix);
+                }
+            }
+            return;
+        }
+        Some("weighted") => {
+            let max_cost = flag_value(&args, "--max-cost", "a number", |n| n.parse().ok());
+            let target = flag_value(&args, "--reach", "a decimal voltage", |n| n.parse().ok());
+
+            let path = input_path.expect("weighted needs an --input of digit:cost pairs");
+
+            let mut battery_lines = String::new();
+            open_input(path)
+                .read_to_string(&mut battery_lines)
+                .unwrap_or_else(|error| exit_unreadable(&error.to_string()));
+
+            let weighted_banks =
+                WeightedBatteryBank::try_parse_multiple_with_radix(&battery_lines, radix)
+                    .unwrap_or_else(|error| exit_unreadable(&error.to_string()));
+
+            for weighted_bank in &weighted_banks {
+                for (label, num_batteries_to_use) in &requested {
+                    let selection = match (max_cost, &target) {
+                        (Some(max_cost), None) => weighted_bank
+                            .select_highest_voltage_within_cost(*num_batteries_to_use, max_cost),
+                        (None, Some(target)) => {
+                            weighted_bank.select_cheapest_reaching(*num_batteries_to_use, target)
+                        }
+                        _ => panic!("weighted needs either --max-cost or --reach"),
+                    };
+
+                    let cost = selection.as_ref().ok().map(|selection| selection.cost);
+
+                    print_selection(
+                        &weighted_bank.bank,
+                        selection.map(|selection| selection.selection),
+                        label,
+                        radix,
+                    );
+
+                    if let Some(cost) = cost {
+                        println!("{label}: Cost: {cost}");
+                    }
+                }
+            }
+            return;
+        }
+        Some("budget") => {
+            let budget = args
+                .get(2)
+                .and_then(|n| n.parse::<usize>().ok())
+                .expect("budget must be followed by a number of batteries");
+
+            let count_after = |flag: &str| flag_value(&args, flag, "a number", |n| n.parse().ok());
+
+            let limits = BankLimits {
+                min: count_after("--min").unwrap_or(0),
+                max: count_after("--max").unwrap_or(usize::MAX),
+            };
+
+            let battery_banks = read_battery_banks();
+
+            match BatteryBank::allocate_budget(
+                &battery_banks,
+                &vec![limits; battery_banks.len()],
+                budget,
+            ) {
+                Ok(allocation) => {
+                    println!(
+                        "The best sum was: {}",
+                        describe_voltage(&allocation.total, radix)
+                    );
+                    println!("Batteries per bank: {:?}", allocation.counts);
+                }
+                Err(error) => println!("Could not allocate the batteries: {error}"),
+            }
+            return;
+        }
+        Some("sweep") => {
+            let totals = BatteryBank::sweep_totals(&read_battery_banks(), policy);
+
+            for (i, total) in totals.iter().enumerate() {
+                match total {
+                    Ok(total) => {
+                        println!("{} batteries: {}", i + 1, describe_voltage(total, radix))
+                    }
+                    Err(error) => println!("{} batteries: {}", i + 1, error),
+                }
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let nums_batteries_to_use: Vec<usize> = requested.iter().map(|(_, n)| *n).collect();
+
+    let sums = match input_path {
+        Some(path) => BatteryBank::sum_highest_voltages_for_each(
+            BatteryBank::read_multiple(open_input(path), radix, options).map(|battery_bank| {
+                battery_bank.unwrap_or_else(|error| exit_unreadable(&error.to_string()))
+            }),
+            &nums_batteries_to_use,
+            policy,
+        ),
+        None => BatteryBank::sum_highest_voltages_for_each(
+            read_battery_banks(),
+            &nums_batteries_to_use,
+            policy,
+        ),
+    };
+
+    for ((label, _), sum) in requested.iter().zip(sums) {
+        match sum {
+            Ok(sum) => println!("{label}: The sum was: {}", describe_voltage(&sum, radix)),
+            Err(error) => println!("{label}: Could not sum the voltages: {error}"),
+        }
+    }
+}
+
+/// Parses the value after the first `flag`, or `None` if it was not given.
+fn flag_value<'a, T>(
+    args: &'a [String],
+    flag: &str,
+    expected: &str,
+    parse: impl FnOnce(&'a str) -> Option<T>,
+) -> Option<T> {
+    let i = args.iter().position(|arg| arg == flag)?;
+
+    Some(value_after(args, i, expected, parse))
+}
+
+/// Parses the value after the flag at `i`, panicking with what was `expected` if it is missing or
+/// does not parse.
+fn value_after<'a, T>(
+    args: &'a [String],
+    i: usize,
+    expected: &str,
+    parse: impl FnOnce(&'a str) -> Option<T>,
+) -> T {
+    args.get(i + 1)
+        .and_then(|value| parse(value))
+        .unwrap_or_else(|| panic!("{} must be followed by {expected}", args[i]))
+}
+
+/// Shows a selection under its bank, or why there is none.
+fn print_selection(
+    battery_bank: &BatteryBank,
+    selection: Result<BatterySelection, BatteryBankError>,
+    label: &str,
+    radix: u32,
+) {
+    match selection {
+        Ok(selection) => {
+            println!("{}", battery_bank.render_selection(&selection));
+            println!(
+                "{label}: Voltage: {}",
+                describe_voltage(&selection.voltage, radix)
+            );
+        }
+        Err(error) => println!("{label}: {error}"),
+    }
+}
+
+fn exit_unreadable(error: &str) -> ! {
+    eprintln!("Could not read the input: {error}");
+    process::exit(1)
+}
+
+/// Opens the file at `path` for reading, or standard input for `-`.
+fn open_input(path: &str) -> Box<dyn BufRead> {
+    if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(
+            File::open(path).unwrap_or_else(|error| exit_unreadable(&error.to_string())),
+        ))
+    }
+}
+
+/// Shows a voltage in decimal, preceded by its digits in `radix` unless that is decimal too.
+fn describe_voltage(voltage: &Voltage, radix: u32) -> String {
+    if radix == 10 {
+        voltage.to_string()
+    } else {
+        format!(
+            "{} in radix {radix} ({voltage} in decimal)",
+            voltage.to_radix_string(radix)
+        )
+    }
+}
+
+fn bench() {
+    const BANK_LENGTH: usize = 1_000_000;
+
+    // A simple linear congruential generator is random enough for benchmark digits
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    let batteries = (0..BANK_LENGTH)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+
+            ((state >> 33) % 9 + 1) as u8
+        })
+        .collect();
+
+    let battery_bank = BatteryBank {
+        batteries,
+        radix: BatteryBank::DEFAULT_RADIX,
+    };
+
+    type FindHighestVoltage = fn(&BatteryBank, usize) -> Result<Voltage, BatteryBankError>;
+
+    for num_batteries_to_use in [2, 12, 19, 100] {
+        let time = |name: &str, find_highest_voltage: FindHighestVoltage| {
+            let started = Instant::now();
+
+            let voltage =
+                find_highest_voltage(hint::black_box(&battery_bank), num_batteries_to_use).unwrap();
+
+            println!(
+                "{name:>10} k={num_batteries_to_use:<2}: {:?} (voltage {voltage})",
+                started.elapsed()
+            );
+        };
+
+        time("rescanning", BatteryBank::find_highest_voltage_rescanning);
+        time("stack", BatteryBank::find_highest_voltage);
+    }
+
+    // Every size at once builds a voltage per size, so a shorter bank keeps this quick
+    let battery_bank = BatteryBank {
+        batteries: battery_bank.batteries[..2_000].to_vec(),
+        ..battery_bank
+    };
+
+    let started = Instant::now();
+    for num_batteries_to_use in 1..=battery_bank.batteries.len() {
+        hint::black_box(battery_bank.find_highest_voltage(num_batteries_to_use)).unwrap();
+    }
+    println!("{:>10} all k: {:?}", "per size", started.elapsed());
+
+    let started = Instant::now();
+    hint::black_box(hint::black_box(&battery_bank).sweep_highest_voltages());
+    println!("{:>10} all k: {:?}", "sweep", started.elapsed());
+}
+
+struct BatteryBank {
+    batteries: Vec<u8>,
+    radix: u32,
+}
+
+/// Reads battery banks line by line, turning each line's bytes into digits in place.
+struct BatteryBankReader<R> {
+    reader: R,
+    radix: u32,
+    options: ParseOptions,
+    line_number: usize,
+}
+
+impl<R: BufRead> Iterator for BatteryBankReader<R> {
+    type Item = io::Result<BatteryBank>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = Vec::new();
+
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(error) => return Some(Err(error)),
+        }
+
+        // Line endings are dropped like `str::lines` does
+        if line.last() == Some(&b'\n') {
+            line.pop();
+
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+
+        self.line_number += 1;
+
+        match BatteryBank::digits_in_place(line, self.radix, self.options) {
+            Ok(batteries) => Some(Ok(BatteryBank {
+                batteries,
+                radix: self.radix,
+            })),
+            Err(error) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ParseError {
+                    line: self.line_number,
+                    ..error
+                },
+            ))),
+        }
+    }
+}
+
+/// Which batteries a voltage is drawn from, in bank order.
+#[derive(PartialEq, Debug)]
+struct BatterySelection {
+    indices: Vec<usize>,
+    digits: Vec<u8>,
+    voltage: Voltage,
+}
+
+/// An exact voltage of any number of digits, stored as base 10^9 limbs, least significant first.
+/// There are never any most significant zero limbs, so zero has no limbs at all.
+#[derive(Clone, PartialEq, Eq, Default)]
+struct Voltage {
+    limbs: Vec<u32>,
+}
+
+impl Voltage {
+    const LIMB_BASE: u64 = 1_000_000_000;
+    const DECIMAL_DIGITS_PER_LIMB: u32 = 9;
+
+    /// Reads `digits`, most significant first, as a number in `radix`.
+    fn from_digits<'a>(digits: impl IntoIterator<Item = &'a u8>, radix: u32) -> Self {
+        let radix = radix as u64;
+
+        if radix.pow(Voltage::DECIMAL_DIGITS_PER_LIMB) == Voltage::LIMB_BASE {
+            return Voltage::from_decimal_digits(digits);
+        }
+
+        let mut voltage = Voltage::default();
+
+        // Digits are gathered into chunks below the limb base, saving a pass per digit
+        let (mut chunk, mut factor) = (0, 1);
+
+        for digit in digits {
+            if factor * radix > Voltage::LIMB_BASE {
+                voltage.multiply_add(factor, chunk);
+                (chunk, factor) = (0, 1);
+            }
+
+            chunk = chunk * radix + *digit as u64;
+            factor *= radix;
+        }
+
+        voltage.multiply_add(factor, chunk);
+        voltage
+    }
+
+    /// Reads decimal `digits`, most significant first, straight into limbs.
+    fn from_decimal_digits<'a>(digits: impl IntoIterator<Item = &'a u8>) -> Self {
+        let digits: Vec<u8> = digits.into_iter().copied().collect();
+
+        let mut voltage = Voltage {
+            limbs: digits
+                .rchunks(Voltage::DECIMAL_DIGITS_PER_LIMB as usize)
+                .map(|chunk| {
+                    chunk
+                        .iter()
+                        .fold(0, |limb, digit| limb * 10 + *digit as u32)
+                })
+                .collect(),
+        };
+
+        while voltage.limbs.last() == Some(&0) {
+            voltage.limbs.pop();
+        }
+
+        voltage
+    }
+
+    /// Sets the voltage to `self * factor + addend`.
+    fn multiply_add(&mut self, factor: u64, addend: u64) {
+        let mut carry = addend;
+
+        for limb in self.limbs.iter_mut() {
+            let value = *limb as u64 * factor + carry;
+
+            *limb = (value % Voltage::LIMB_BASE) as u32;
+            carry = value / Voltage::LIMB_BASE;
+        }
+
+        while carry > 0 {
+            self.limbs.push((carry % Voltage::LIMB_BASE) as u32);
+            carry /= Voltage::LIMB_BASE;
+        }
+    }
+
+    /// Divides the voltage by `divisor`, returning the remainder.
+    fn divide_small(&mut self, divisor: u64) -> u64 {
+        let mut remainder = 0;
+
+        for limb in self.limbs.iter_mut().rev() {
+            let value = remainder * Voltage::LIMB_BASE + *limb as u64;
+
+            *limb = (value / divisor) as u32;
+            remainder = value % divisor;
+        }
+
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+
+        remainder
+    }
+
+    /// Writes the voltage with lowercase digits in `radix`.
+    fn to_radix_string(&self, radix: u32) -> String {
+        let digits = self.to_digits(radix);
+
+        if digits.is_empty() {
+            return "0".to_string();
+        }
+
+        digits
+            .iter()
+            .map(|digit| char::from_digit(*digit as u32, radix).unwrap())
+            .collect()
+    }
+
+    /// The digits of the voltage in `radix` padded with leading zeros to `num_digits`, or `None`
+    /// if there are more digits than that.
+    fn to_padded_digits(&self, radix: u32, num_digits: usize) -> Option<Vec<u8>> {
+        let mut digits = self.to_digits(radix);
+
+        let padding = num_digits.checked_sub(digits.len())?;
+        digits.splice(0..0, iter::repeat_n(0, padding));
+
+        Some(digits)
+    }
+
+    /// The digits of the voltage in `radix`, most significant first. Zero has no digits.
+    fn to_digits(&self, radix: u32) -> Vec<u8> {
+        let mut rest = self.clone();
+        let mut digits = Vec::new();
+
+        while !rest.limbs.is_empty() {
+            digits.push(rest.divide_small(radix as u64) as u8);
+        }
+
+        digits.reverse();
+        digits
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        self.limbs.iter().rev().try_fold(0u64, |value, limb| {
+            value
+                .checked_mul(Voltage::LIMB_BASE)?
+                .checked_add(*limb as u64)
+        })
+    }
+}
+
+impl From<u64> for Voltage {
+    fn from(value: u64) -> Self {
+        let mut voltage = Voltage::default();
+        voltage.multiply_add(1, value);
+        voltage
+    }
+}
+
+impl FromStr for Voltage {
+    type Err = ParseVoltageError;
+
+    /// Reads a decimal voltage of any number of digits.
+    fn from_str(voltage: &str) -> Result<Self, Self::Err> {
+        let digits: Vec<u8> = voltage
+            .chars()
+            .map(|c| c.to_digit(10).map(|digit| digit as u8))
+            .collect::<Option<_>>()
+            .filter(|digits: &Vec<u8>| !digits.is_empty())
+            .ok_or(ParseVoltageError)?;
+
+        Ok(Voltage::from_decimal_digits(&digits))
+    }
+}
+
+impl PartialEq<u64> for Voltage {
+    fn eq(&self, other: &u64) -> bool {
+        self.to_u64() == Some(*other)
+    }
+}
+
+impl AddAssign<&Voltage> for Voltage {
+    fn add_assign(&mut self, rhs: &Voltage) {
+        if self.limbs.len() < rhs.limbs.len() {
+            self.limbs.resize(rhs.limbs.len(), 0);
+        }
+
+        let mut carry = 0;
+
+        for (i, limb) in self.limbs.iter_mut().enumerate() {
+            let value = *limb as u64 + rhs.limbs.get(i).copied().unwrap_or(0) as u64 + carry;
+
+            *limb = (value % Voltage::LIMB_BASE) as u32;
+            carry = value / Voltage::LIMB_BASE;
+        }
+
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+    }
+}
+
+impl Add<&Voltage> for Voltage {
+    type Output = Voltage;
+
+    fn add(mut self, rhs: &Voltage) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl Sum for Voltage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Voltage::default(), |sum, voltage| sum + &voltage)
+    }
+}
+
+impl Ord for Voltage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for Voltage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Voltage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((most_significant, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+
+        write!(f, "{most_significant}")?;
+
+        for limb in rest.iter().rev() {
+            write!(f, "{limb:09}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Voltage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum BatteryBankError {
+    /// The bank has no batteries at all, e.g. because its line was empty.
+    EmptyBank,
+    NotEnoughBatteries {
+        available: usize,
+        requested: usize,
+    },
+    /// No selection of batteries meets the objective.
+    Infeasible,
+    /// A [`TieBreak::LeastWorn`] with a wear count for a different number of batteries.
+    WearCountMismatch {
+        batteries: usize,
+        wear_counts: usize,
+    },
+    /// The table of remainders for a [`VoltageObjective::DivisibleBy`] would not fit in
+    /// [`RemainderSets::MAX_BYTES`].
+    DivisorTooLarge {
+        divisor: u64,
+    },
+}
+
+impl fmt::Display for BatteryBankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatteryBankError::EmptyBank => write!(f, "The battery bank is empty"),
+            BatteryBankError::NotEnoughBatteries {
+                available,
+                requested,
+            } => write!(
+                f,
+                "Cannot use {requested} batteries from a bank of {available}"
+            ),
+            BatteryBankError::Infeasible => {
+                write!(f, "No selection of batteries meets the objective")
+            }
+            BatteryBankError::WearCountMismatch {
+                batteries,
+                wear_counts,
+            } => write!(
+                f,
+                "Got {wear_counts} wear counts for a bank of {batteries} batteries"
+            ),
+            BatteryBankError::DivisorTooLarge { divisor } => write!(
+                f,
+                "The divisor {divisor} is too large for a bank of this many batteries"
+            ),
+        }
+    }
+}
+
+impl Error for BatteryBankError {}
+
+/// A character that is not a battery, with its 1-based line and column.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct ParseError {
+    line: usize,
+    column: usize,
+    character: char,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid battery {:?} at line {}, column {}",
+            self.character, self.line, self.column
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// A voltage that is not made of decimal digits only.
+#[derive(PartialEq, Debug)]
+struct ParseVoltageError;
+
+impl fmt::Display for ParseVoltageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "A voltage must be one or more decimal digits")
+    }
+}
+
+impl Error for ParseVoltageError {}
+
+/// Which stray characters around the batteries of a line are dropped instead of reported.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+struct ParseOptions {
+    /// A `\r` ending the line, as left over from Windows line endings.
+    strip_carriage_return: bool,
+    /// Whitespace before and after the batteries.
+    trim_whitespace: bool,
+}
+
+/// How many batteries may be taken from one bank.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct BankLimits {
+    min: usize,
+    max: usize,
+}
+
+/// How many batteries to take from each bank, and the summed voltage that gives.
+#[derive(PartialEq, Debug)]
+struct BudgetAllocation {
+    counts: Vec<usize>,
+    total: Voltage,
+}
+
+/// What to do with banks holding fewer batteries than should be used.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum ShortBankPolicy {
+    /// Leave the bank out.
+    Skip,
+    /// Fail with a [`BatteryBankError`].
+    Error,
+    /// Use every battery the bank has.
+    UseAll,
+}
+
+impl ShortBankPolicy {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(ShortBankPolicy::Skip),
+            "error" => Some(ShortBankPolicy::Error),
+            "use-all" => Some(ShortBankPolicy::UseAll),
+            _ => None,
+        }
+    }
+}
+
+/// What a selection of batteries should achieve.
+#[derive(PartialEq, Debug, Clone)]
+enum VoltageObjective {
+    Highest,
+    /// With `allow_leading_zero` unset, the first selected battery must not be a zero, since that
+    /// would effectively use fewer batteries.
+    Lowest {
+        allow_leading_zero: bool,
+    },
+    HighestAtMost(Voltage),
+    HighestDivisibleBy(u64),
+}
+
+impl VoltageObjective {
+    /// Parses `highest`, `lowest`, `lowest-with-zero`, `at-most:N` and `divisible-by:N`, with a
+    /// decimal `N`.
+    fn parse(name: &str) -> Option<Self> {
+        match name.split_once(':') {
+            None => match name {
+                "highest" => Some(VoltageObjective::Highest),
+                "lowest" => Some(VoltageObjective::Lowest {
+                    allow_leading_zero: false,
+                }),
+                "lowest-with-zero" => Some(VoltageObjective::Lowest {
+                    allow_leading_zero: true,
+                }),
+                _ => None,
+            },
+            Some(("at-most", threshold)) => {
+                threshold.parse().ok().map(VoltageObjective::HighestAtMost)
+            }
+            Some(("divisible-by", divisor)) => divisor
+                .parse()
+                .ok()
+                .map(VoltageObjective::HighestDivisibleBy),
+            Some(_) => None,
+        }
+    }
+}
+
+/// How the selected batteries must be spaced, by the difference between consecutive indices.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum SelectionConstraint {
+    /// Consecutive selected batteries are at most this far apart.
+    MaxGap(usize),
+    /// Consecutive selected batteries are at least this far apart.
+    MinSpacing(usize),
+    /// The selected batteries sit next to each other.
+    Contiguous,
+}
+
+impl SelectionConstraint {
+    /// Parses `contiguous`, `max-gap:N` and `min-spacing:N`.
+    fn parse(name: &str) -> Option<Self> {
+        match name.split_once(':') {
+            None => (name == "contiguous").then_some(SelectionConstraint::Contiguous),
+            Some(("max-gap", max_gap)) => max_gap.parse().ok().map(SelectionConstraint::MaxGap),
+            Some(("min-spacing", min_spacing)) => min_spacing
+                .parse()
+                .ok()
+                .map(SelectionConstraint::MinSpacing),
+            Some(_) => None,
+        }
+    }
+
+    /// The smallest and largest allowed difference between consecutive selected indices.
+    fn gap_bounds(&self) -> (usize, usize) {
+        match *self {
+            SelectionConstraint::MaxGap(max_gap) => (1, max_gap),
+            SelectionConstraint::MinSpacing(min_spacing) => (min_spacing.max(1), usize::MAX),
+            SelectionConstraint::Contiguous => (1, 1),
+        }
+    }
+}
+
+/// Which battery to pick among equally high ones that all lead to the same voltage.
+#[derive(PartialEq, Debug, Clone)]
+enum TieBreak {
+    Earliest,
+    Latest,
+    /// The battery with the lowest wear count, indexed like the bank's batteries, and the
+    /// earliest of those.
+    LeastWorn(Vec<u64>),
+}
+
+impl TieBreak {
+    /// Parses the tie breaks that need no wear counts.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "earliest" => Some(TieBreak::Earliest),
+            "latest" => Some(TieBreak::Latest),
+            _ => None,
+        }
+    }
+
+    /// Whether the battery at `index` is picked over the one at `other`.
+    fn prefers(&self, index: usize, other: usize) -> bool {
+        match self {
+            TieBreak::Earliest => index < other,
+            TieBreak::Latest => index > other,
+            TieBreak::LeastWorn(wear) => (wear[index], index) < (wear[other], other),
+        }
+    }
+}
+
+struct FindDigitResult {
+    digit: u8,
+    index: usize,
+}
+
+/// Sets of remainders modulo a divisor, each packed into one bit per remainder.
+struct RemainderSets {
+    divisor: usize,
+    words_per_set: usize,
+    words: Vec<u64>,
+}
+
+impl RemainderSets {
+    /// The most memory the sets may take up.
+    const MAX_BYTES: usize = 1 << 28;
+
+    /// Makes `num_sets` empty sets, or `None` if they would take up more than
+    /// [`RemainderSets::MAX_BYTES`].
+    fn new(num_sets: usize, divisor: u64) -> Option<Self> {
+        let divisor = usize::try_from(divisor).ok()?;
+        let words_per_set = divisor.div_ceil(64);
+
+        let num_words = num_sets.checked_mul(words_per_set)?;
+
+        if num_words.checked_mul(8)? > Self::MAX_BYTES {
+            return None;
+        }
+
+        Some(RemainderSets {
+            divisor,
+            words_per_set,
+            words: vec![0; num_words],
+        })
+    }
+
+    fn contains(&self, set: usize, remainder: usize) -> bool {
+        let bit = set * self.words_per_set * 64 + remainder;
+
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn insert(&mut self, set: usize, remainder: usize) {
+        let bit = set * self.words_per_set * 64 + remainder;
+
+        self.words[bit / 64] |= 1 << (bit % 64);
+    }
+
+    /// Makes `into` the union of `skipped` and of `used` with `shift` added to every remainder.
+    /// Both sources must come after `into`.
+    fn union_into(&mut self, into: usize, skipped: usize, used: usize, shift: usize) {
+        let words_per_set = self.words_per_set;
+
+        let (before, after) = self.words.split_at_mut((into + 1) * words_per_set);
+
+        let into_words = &mut before[into * words_per_set..];
+        let source = |set: usize| &after[(set - into - 1) * words_per_set..][..words_per_set];
+
+        into_words.copy_from_slice(source(skipped));
+
+        // Remainders that pass the divisor wrap around to the start
+        let wrap = self.divisor - shift;
+        Self::union_bits(into_words, shift, source(used), 0, wrap);
+        Self::union_bits(into_words, 0, source(used), wrap, shift);
+    }
+
+    /// Sets the `len` bits of `into` from `into_start` on wherever they are set in `from` from
+    /// `from_start` on.
+    fn union_bits(
+        into: &mut [u64],
+        into_start: usize,
+        from: &[u64],
+        from_start: usize,
+        len: usize,
+    ) {
+        let mut done = 0;
+
+        while done < len {
+            let bit = into_start + done;
+            let count = (64 - bit % 64).min(len - done);
+
+            let bits = Self::word_at(from, from_start + done) & (u64::MAX >> (64 - count));
+            into[bit / 64] |= bits << (bit % 64);
+
+            done += count;
+        }
+    }
+
+    /// The 64 bits from `bit` on, with zeros past the end of `words`.
+    fn word_at(words: &[u64], bit: usize) -> u64 {
+        let (index, offset) = (bit / 64, bit % 64);
+
+        let low = words.get(index).map_or(0, |word| word >> offset);
+        let high = match offset {
+            0 => 0,
+            _ => words.get(index + 1).map_or(0, |word| word << (64 - offset)),
+        };
+
+        low | high
+    }
+}
+
+impl BatteryBank {
+    const DEFAULT_RADIX: u32 = 10;
+    /// The radixes `char::to_digit` can read digits in.
+    const RADIXES: RangeInclusive<u32> = 2..=36;
+
+    #[cfg(test)]
+    fn parse(battery_line: &str) -> Self {
+        BatteryBank::parse_with_radix(battery_line, BatteryBank::DEFAULT_RADIX)
+    }
+
+    /// Reads the batteries as digits in `radix`, which must be within `2..=36`.
+    #[cfg(test)]
+    fn parse_with_radix(battery_line: &str, radix: u32) -> Self {
+        BatteryBank::try_parse_with_radix(battery_line, radix, ParseOptions::default())
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Reads the batteries as digits in `radix`, reporting the first invalid character as being
+    /// on line 1.
+    fn try_parse_with_radix(
+        battery_line: &str,
+        radix: u32,
+        options: ParseOptions,
+    ) -> Result<Self, ParseError> {
+        BatteryBank::check_radix(radix);
+
+        Ok(Self {
+            batteries: BatteryBank::digits_in_place(
+                battery_line.as_bytes().to_vec(),
+                radix,
+                options,
+            )?,
+            radix,
+        })
+    }
+
+    /// Turns the bytes of a line into digits in place, once normalized as `options` allow.
+    ///
+    /// Digits are ASCII, so everything before the first invalid character is one byte per
+    /// character and its column can be counted in bytes.
+    fn digits_in_place(
+        mut line: Vec<u8>,
+        radix: u32,
+        options: ParseOptions,
+    ) -> Result<Vec<u8>, ParseError> {
+        if options.strip_carriage_return && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        let mut start = 0;
+
+        if options.trim_whitespace {
+            let trailing = line
+                .iter()
+                .rev()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+            line.truncate(line.len() - trailing);
+
+            start = line
+                .iter()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .count();
+        }
+
+        for index in start..line.len() {
+            match BatteryBank::char_to_digit(char::from(line[index]), radix) {
+                Some(digit) => line[index] = digit,
+                None => {
+                    return Err(ParseError {
+                        line: 1,
+                        column: index + 1,
+                        character: String::from_utf8_lossy(&line[index..])
+                            .chars()
+                            .next()
+                            .unwrap(),
+                    });
+                }
+            }
+        }
+
+        line.drain(..start);
+
+        Ok(line)
+    }
+
+    /// Finds the highest digit, picking among equally high ones by `tie_break`. The digits start
+    /// at index `start` of the bank, which is what wear counts are looked up by.
+    fn find_highest_digit(
+        digits: &[u8],
+        start: usize,
+        tie_break: &TieBreak,
+    ) -> Option<FindDigitResult> {
+        let mut highest = FindDigitResult {
+            digit: *digits.first()?,
+            index: 0,
+        };
+
+        for (index, digit) in digits.iter().enumerate() {
+            if *digit > highest.digit
+                || (*digit == highest.digit
+                    && tie_break.prefers(start + index, start + highest.index))
+            {
+                highest = FindDigitResult {
+                    digit: *digit,
+                    index,
+                }
+            }
+        }
+
+        Some(highest)
+    }
+
+    fn check_radix(radix: u32) {
+        assert!(
+            BatteryBank::RADIXES.contains(&radix),
+            "The radix must be between 2 and 36"
+        );
+    }
+
+    fn char_to_digit(c: char, radix: u32) -> Option<u8> {
+        c.to_digit(radix).map(|digit| digit as u8)
+    }
+
+    fn find_highest_voltage(
+        &self,
+        num_batteries_to_use: usize,
+    ) -> Result<Voltage, BatteryBankError> {
+        Ok(self.select_highest_voltage(num_batteries_to_use)?.voltage)
+    }
+
+    fn check_num_batteries_to_use(
+        &self,
+        num_batteries_to_use: usize,
+    ) -> Result<(), BatteryBankError> {
+        if num_batteries_to_use == 0 {
+            return Ok(());
+        }
+
+        if self.batteries.is_empty() {
+            return Err(BatteryBankError::EmptyBank);
+        }
+
+        if num_batteries_to_use > self.batteries.len() {
+            return Err(BatteryBankError::NotEnoughBatteries {
+                available: self.batteries.len(),
+                requested: num_batteries_to_use,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Like [`BatteryBank::find_highest_voltage`], but short banks are handled by `policy`.
+    /// Skipped banks give `None`.
+    fn find_highest_voltage_with_policy(
+        &self,
+        num_batteries_to_use: usize,
+        policy: ShortBankPolicy,
+    ) -> Result<Option<Voltage>, BatteryBankError> {
+        let is_short = num_batteries_to_use > self.batteries.len();
+
+        match policy {
+            ShortBankPolicy::Skip if is_short => Ok(None),
+            ShortBankPolicy::UseAll if is_short => {
+                self.find_highest_voltage(self.batteries.len()).map(Some)
+            }
+            _ => self.find_highest_voltage(num_batteries_to_use).map(Some),
+        }
+    }
+
+    /// Sums the highest voltages of the banks for each of `nums_batteries_to_use` in a single
+    /// pass over the banks. Each sum stops at its first error.
+    fn sum_highest_voltages_for_each(
+        battery_banks: impl IntoIterator<Item = impl Borrow<Self>>,
+        nums_batteries_to_use: &[usize],
+        policy: ShortBankPolicy,
+    ) -> Vec<Result<Voltage, BatteryBankError>> {
+        let mut sums: Vec<Result<Voltage, BatteryBankError>> = nums_batteries_to_use
+            .iter()
+            .map(|_| Ok(Voltage::default()))
+            .collect();
+
+        for battery_bank in battery_banks {
+            for (sum, num_batteries_to_use) in sums.iter_mut().zip(nums_batteries_to_use) {
+                let Ok(total) = sum else {
+                    continue;
+                };
+
+                match battery_bank
+                    .borrow()
+                    .find_highest_voltage_with_policy(*num_batteries_to_use, policy)
+                {
+                    Ok(Some(voltage)) => *total += &voltage,
+                    Ok(None) => {}
+                    Err(error) => *sum = Err(error),
+                }
+            }
+        }
+
+        sums
+    }
+
+    /// The highest voltage for every number of batteries to use, indexed by that number.
+    ///
+    /// The best selection of one battery less is always the current selection without its first
+    /// battery that is lower than the one after it, or without its last battery if there is no
+    /// such battery. So starting from all batteries, every selection follows by removing one.
+    fn sweep_highest_voltages(&self) -> Vec<Voltage> {
+        let num_batteries = self.batteries.len();
+
+        // The kept batteries as a linked list, with num_batteries marking its end
+        let mut first = 0;
+        let mut next: Vec<usize> = (1..=num_batteries).collect();
+        let mut previous: Vec<Option<usize>> =
+            (0..num_batteries).map(|i| i.checked_sub(1)).collect();
+
+        let mut voltages = vec![Voltage::default(); num_batteries + 1];
+
+        // Every kept battery before the cursor is at least as high as the one after it
+        let mut cursor = 0;
+
+        for num_batteries_to_use in (1..=num_batteries).rev() {
+            let kept = iter::successors(Some(first), |index| {
+                Some(next[*index]).filter(|index| *index != num_batteries)
+            })
+            .map(|index| &self.batteries[index]);
+
+            voltages[num_batteries_to_use] = Voltage::from_digits(kept, self.radix);
+
+            while next[cursor] != num_batteries
+                && self.batteries[cursor] >= self.batteries[next[cursor]]
+            {
+                cursor = next[cursor];
+            }
+
+            let (before, after) = (previous[cursor], next[cursor]);
+
+            match before {
+                Some(before) => next[before] = after,
+                None => first = after,
+            }
+
+            if after != num_batteries {
+                previous[after] = before;
+            }
+
+            // The battery before the removed one now has a new one after it to compare with
+            cursor = before.unwrap_or(after);
+        }
+
+        voltages
+    }
+
+    /// The summed highest voltages of all banks for every number of batteries to use, from one up
+    /// to the length of the longest bank. Short banks are handled by `policy`.
+    fn sweep_totals(
+        battery_banks: &[Self],
+        policy: ShortBankPolicy,
+    ) -> Vec<Result<Voltage, BatteryBankError>> {
+        let sweeps: Vec<Vec<Voltage>> = battery_banks
+            .iter()
+            .map(BatteryBank::sweep_highest_voltages)
+            .collect();
+
+        let max_batteries = battery_banks
+            .iter()
+            .map(|battery_bank| battery_bank.batteries.len())
+            .max()
+            .unwrap_or(0);
+
+        (1..=max_batteries)
+            .map(|num_batteries_to_use| {
+                let mut total = Voltage::default();
+
+                for (battery_bank, sweep) in battery_banks.iter().zip(&sweeps) {
+                    let voltage = match sweep.get(num_batteries_to_use) {
+                        Some(voltage) => voltage,
+                        None => match policy {
+                            ShortBankPolicy::Skip => continue,
+                            // Always holds at least the voltage for using no batteries
+                            ShortBankPolicy::UseAll => sweep.last().unwrap(),
+                            ShortBankPolicy::Error => {
+                                battery_bank.check_num_batteries_to_use(num_batteries_to_use)?;
+                                unreachable!("A short bank is always an error")
+                            }
+                        },
+                    };
+
+                    total += voltage;
+                }
+
+                Ok(total)
+            })
+            .collect()
+    }
+
+    /// Decides how many batteries to take from each bank, within its `limits` and at most `budget`
+    /// in total, for the highest summed voltage.
+    ///
+    /// Works through the banks keeping the best sum for every number of batteries used so far,
+    /// extending each by every count the next bank allows. The voltages for those counts all
+    /// come from a single sweep of the bank.
+    fn allocate_budget(
+        battery_banks: &[Self],
+        limits: &[BankLimits],
+        budget: usize,
+    ) -> Result<BudgetAllocation, BatteryBankError> {
+        assert_eq!(battery_banks.len(), limits.len(), "Every bank needs limits");
+
+        let most_usable = battery_banks
+            .iter()
+            .zip(limits)
+            .map(|(battery_bank, limits)| limits.max.min(battery_bank.batteries.len()))
+            .sum();
+        let budget = budget.min(most_usable);
+
+        let mut best: Vec<Option<Voltage>> = vec![None; budget + 1];
+        best[0] = Some(Voltage::default());
+
+        // For every bank, how many of its batteries the best sum for each total used takes
+        let mut choices: Vec<Vec<usize>> = Vec::with_capacity(battery_banks.len());
+
+        for (battery_bank, limits) in battery_banks.iter().zip(limits) {
+            battery_bank.check_num_batteries_to_use(limits.min)?;
+
+            let voltages = battery_bank.sweep_highest_voltages();
+            let max = limits.max.min(battery_bank.batteries.len());
+
+            let mut next_best: Vec<Option<Voltage>> = vec![None; budget + 1];
+            let mut choice = vec![0; budget + 1];
+
+            for (used, sum) in best.iter().enumerate() {
+                let Some(sum) = sum else {
+                    continue;
+                };
+
+                for count in limits.min..=max.min(budget - used) {
+                    let candidate = sum.clone() + &voltages[count];
+
+                    if next_best[used + count]
+                        .as_ref()
+                        .is_none_or(|current| candidate > *current)
+                    {
+                        next_best[used + count] = Some(candidate);
+                        choice[used + count] = count;
+                    }
+                }
+            }
+
+            best = next_best;
+            choices.push(choice);
+        }
+
+        // The fewest batteries giving the highest sum
+        let (mut used, total) = best
+            .into_iter()
+            .enumerate()
+            .filter_map(|(used, sum)| Some((used, sum?)))
+            .rev()
+            .max_by(|(_, sum), (_, other)| sum.cmp(other))
+            .ok_or(BatteryBankError::Infeasible)?;
+
+        let mut counts = vec![0; battery_banks.len()];
+
+        for (count, choice) in counts.iter_mut().zip(&choices).rev() {
+            *count = choice[used];
+            used -= *count;
+        }
+
+        Ok(BudgetAllocation { counts, total })
+    }
+
+    /// Picks the batteries greedily in a single pass: a battery is dropped whenever a higher one
+    /// comes after it, as long as enough batteries remain to fill up the selection.
+    fn select_highest_voltage(
+        &self,
+        num_batteries_to_use: usize,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        let indices = self.select_greedily(0, num_batteries_to_use, |last, battery| last < battery);
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    /// Finds the highest voltage like [`BatteryBank::select_highest_voltage`], but picks among
+    /// the batteries that lead to it by `tie_break`.
+    ///
+    /// Each battery is picked from the ones up to where the rest of the voltage's digits can
+    /// still be found last in the bank, so every pick keeps the voltage the highest.
+    fn select_highest_voltage_with_tie_break(
+        &self,
+        num_batteries_to_use: usize,
+        tie_break: &TieBreak,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        if let TieBreak::LeastWorn(wear) = tie_break
+            && wear.len() != self.batteries.len()
+        {
+            return Err(BatteryBankError::WearCountMismatch {
+                batteries: self.batteries.len(),
+                wear_counts: wear.len(),
+            });
+        }
+
+        let digits = self.select_highest_voltage(num_batteries_to_use)?.digits;
+
+        // The last index each digit can be found at, with the digits after it found after it
+        let mut latest_indices = vec![0; digits.len()];
+        let mut end = self.batteries.len();
+
+        for (j, digit) in digits.iter().enumerate().rev() {
+            // The digits are a selection from the bank, so they are always found
+            end = self.batteries[..end]
+                .iter()
+                .rposition(|battery| battery == digit)
+                .unwrap();
+
+            latest_indices[j] = end;
+        }
+
+        let mut indices = Vec::with_capacity(digits.len());
+        let mut start = 0;
+
+        for latest_index in latest_indices {
+            let allowed_batteries = &self.batteries[start..=latest_index];
+
+            let result =
+                BatteryBank::find_highest_digit(allowed_batteries, start, tie_break).unwrap();
+
+            indices.push(start + result.index);
+            start += result.index + 1;
+        }
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    fn select_voltage(
+        &self,
+        num_batteries_to_use: usize,
+        objective: &VoltageObjective,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        match objective {
+            VoltageObjective::Highest => self.select_highest_voltage(num_batteries_to_use),
+            VoltageObjective::Lowest { allow_leading_zero } => {
+                self.select_lowest_voltage(num_batteries_to_use, *allow_leading_zero)
+            }
+            VoltageObjective::HighestAtMost(threshold) => {
+                self.select_highest_voltage_at_most(num_batteries_to_use, threshold)
+            }
+            VoltageObjective::HighestDivisibleBy(divisor) => {
+                self.select_highest_voltage_divisible_by(num_batteries_to_use, *divisor)
+            }
+        }
+    }
+
+    fn select_lowest_voltage(
+        &self,
+        num_batteries_to_use: usize,
+        allow_leading_zero: bool,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        if allow_leading_zero || num_batteries_to_use == 0 {
+            let indices =
+                self.select_greedily(0, num_batteries_to_use, |last, battery| last > battery);
+
+            return Ok(self.selection_from_indices(indices));
+        }
+
+        // The lowest non-zero first battery, leaving enough batteries after it for the rest
+        let first_index = (0..=self.batteries.len() - num_batteries_to_use)
+            .filter(|index| self.batteries[*index] != 0)
+            .min_by_key(|index| self.batteries[*index])
+            .ok_or(BatteryBankError::Infeasible)?;
+
+        let mut indices = vec![first_index];
+        indices.extend(self.select_greedily(
+            first_index + 1,
+            num_batteries_to_use - 1,
+            |last, battery| last > battery,
+        ));
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    /// Finds the highest voltage not exceeding `threshold`.
+    ///
+    /// Picks batteries from the highest digit down, checking against a table of which positions
+    /// can still be completed without going over the threshold's remaining digits.
+    fn select_highest_voltage_at_most(
+        &self,
+        num_batteries_to_use: usize,
+        threshold: &Voltage,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        let Some(limit) = threshold.to_padded_digits(self.radix, num_batteries_to_use) else {
+            return self.select_highest_voltage(num_batteries_to_use);
+        };
+
+        let num_batteries = self.batteries.len();
+        let k = num_batteries_to_use;
+
+        // Whether the batteries from index i on can fill positions j.. without exceeding the limit
+        let mut can_stay_within = vec![vec![false; k + 1]; num_batteries + 1];
+
+        for i in (0..=num_batteries).rev() {
+            can_stay_within[i][k] = true;
+
+            if i == num_batteries {
+                continue;
+            }
+
+            for j in 0..k {
+                let battery = self.batteries[i];
+
+                can_stay_within[i][j] = can_stay_within[i + 1][j]
+                    || (battery < limit[j] && num_batteries - i > k - j - 1)
+                    || (battery == limit[j] && can_stay_within[i + 1][j + 1]);
+            }
+        }
+
+        if !can_stay_within[0][0] {
+            return Err(BatteryBankError::Infeasible);
+        }
+
+        let mut indices = Vec::with_capacity(k);
+
+        let mut is_tight = true;
+        let mut next_index = 0;
+
+        for j in 0..k {
+            let max_digit = if is_tight {
+                limit[j]
+            } else {
+                self.radix as u8 - 1
+            };
+
+            let chosen = (0..=max_digit).rev().find_map(|digit| {
+                let index = self.find_next(next_index, digit, k - j - 1)?;
+
+                let is_completable =
+                    !is_tight || digit < limit[j] || can_stay_within[index + 1][j + 1];
+
+                is_completable.then_some(index)
+            });
+
+            // Every chosen battery leaves a way to stay within the limit, so the next one exists
+            let index = chosen.unwrap();
+
+            is_tight = is_tight && self.batteries[index] == limit[j];
+            next_index = index + 1;
+
+            indices.push(index);
+        }
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    /// Finds the highest voltage that is a multiple of `divisor`.
+    ///
+    /// Picks batteries from the highest digit down, checking against a table of which remainders
+    /// the batteries after each position can still produce. The table has a bit per remainder, so
+    /// a divisor that would make it larger than [`RemainderSets::MAX_BYTES`] is refused.
+    fn select_highest_voltage_divisible_by(
+        &self,
+        num_batteries_to_use: usize,
+        divisor: u64,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        if divisor == 0 {
+            return Err(BatteryBankError::Infeasible);
+        }
+
+        let num_batteries = self.batteries.len();
+        let k = num_batteries_to_use;
+
+        // The remainders j batteries from index i on can make up
+        let set = |i: usize, j: usize| i * (k + 1) + j;
+        let mut reachable = RemainderSets::new(set(num_batteries + 1, 0), divisor)
+            .ok_or(BatteryBankError::DivisorTooLarge { divisor })?;
+
+        // The place value of the digit with j digits after it, modulo the divisor
+        let mut place_values = vec![1 % divisor; k + 1];
+        for j in 1..=k {
+            place_values[j] = place_values[j - 1] * self.radix as u64 % divisor;
+        }
+
+        for i in (0..=num_batteries).rev() {
+            reachable.insert(set(i, 0), 0);
+
+            if i == num_batteries {
+                continue;
+            }
+
+            let battery = self.batteries[i] as u64;
+
+            for j in 1..=k {
+                let shift = battery * place_values[j - 1] % divisor;
+
+                reachable.union_into(set(i, j), set(i + 1, j), set(i + 1, j - 1), shift as usize);
+            }
+        }
+
+        if !reachable.contains(set(0, k), 0) {
+            return Err(BatteryBankError::Infeasible);
+        }
+
+        let mut indices = Vec::with_capacity(k);
+
+        let mut remainder = 0;
+        let mut next_index = 0;
+
+        for j in (1..=k).rev() {
+            let chosen = (0..self.radix as u8).rev().find_map(|digit| {
+                let index = self.find_next(next_index, digit, j - 1)?;
+
+                let rest =
+                    (remainder + divisor - digit as u64 * place_values[j - 1] % divisor) % divisor;
+
+                reachable
+                    .contains(set(index + 1, j - 1), rest as usize)
+                    .then_some((index, rest))
+            });
+
+            // The remainder left to make up is always one the batteries after can reach
+            let (index, rest) = chosen.unwrap();
+
+            remainder = rest;
+            next_index = index + 1;
+
+            indices.push(index);
+        }
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    #[cfg(test)]
+    fn find_highest_voltage_with_constraint(
+        &self,
+        num_batteries_to_use: usize,
+        constraint: &SelectionConstraint,
+    ) -> Result<Voltage, BatteryBankError> {
+        self.select_highest_voltage_with_constraint(num_batteries_to_use, constraint)
+            .map(|selection| selection.voltage)
+    }
+
+    /// Finds the highest voltage whose batteries are spaced as `constraint` requires.
+    ///
+    /// Ranks the best voltages of each length starting at each battery, one length at a time. A
+    /// voltage one battery longer starts with its battery followed by the best ranked voltage
+    /// within the allowed gap, which a sliding window maximum finds for every start in one pass.
+    fn select_highest_voltage_with_constraint(
+        &self,
+        num_batteries_to_use: usize,
+        constraint: &SelectionConstraint,
+    ) -> Result<BatterySelection, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        if num_batteries_to_use == 0 {
+            return Ok(self.selection_from_indices(Vec::new()));
+        }
+
+        let (min_gap, max_gap) = constraint.gap_bounds();
+        let num_batteries = self.batteries.len();
+
+        // Ranks are only compared among voltages of the same length, None where none fits
+        let mut ranks: Vec<Option<usize>> = self
+            .batteries
+            .iter()
+            .map(|battery| Some(*battery as usize))
+            .collect();
+
+        // For every length above one, the battery following each start in its best voltage
+        let mut followers: Vec<Vec<usize>> = Vec::with_capacity(num_batteries_to_use - 1);
+
+        for _ in 1..num_batteries_to_use {
+            let mut keys: Vec<Option<(u8, usize)>> = vec![None; num_batteries];
+            let mut following = vec![0; num_batteries];
+
+            // Indices in the window, from the furthest to the nearest, with decreasing ranks
+            let mut window: VecDeque<usize> = VecDeque::new();
+
+            for start in (0..num_batteries).rev() {
+                if let Some(entering) = start.checked_add(min_gap).filter(|i| *i < num_batteries)
+                    && let Some(rank) = ranks[entering]
+                {
+                    // The nearer battery stays in the window longer, so it wins ties
+                    while window.back().is_some_and(|i| ranks[*i] <= Some(rank)) {
+                        window.pop_back();
+                    }
+
+                    window.push_back(entering);
+                }
+
+                while window
+                    .front()
+                    .is_some_and(|i| *i > start.saturating_add(max_gap))
+                {
+                    window.pop_front();
+                }
+
+                if let Some(&best) = window.front() {
+                    keys[start] = Some((self.batteries[start], ranks[best].unwrap()));
+                    following[start] = best;
+                }
+            }
+
+            let mut sorted_keys: Vec<(u8, usize)> = keys.iter().flatten().copied().collect();
+            sorted_keys.sort_unstable();
+            sorted_keys.dedup();
+
+            ranks = keys
+                .iter()
+                .map(|key| key.map(|key| sorted_keys.binary_search(&key).unwrap()))
+                .collect();
+
+            followers.push(following);
+        }
+
+        // The earliest of the best starts, like the other selections
+        let mut index = (0..num_batteries)
+            .filter(|i| ranks[*i].is_some())
+            .rev()
+            .max_by_key(|i| ranks[*i])
+            .ok_or(BatteryBankError::Infeasible)?;
+
+        let mut indices = vec![index];
+
+        for following in followers.iter().rev() {
+            index = following[index];
+            indices.push(index);
+        }
+
+        Ok(self.selection_from_indices(indices))
+    }
+
+    /// The first index from `start` with the given digit that leaves at least `num_after`
+    /// batteries after it.
+    fn find_next(&self, start: usize, digit: u8, num_after: usize) -> Option<usize> {
+        let end = self.batteries.len().checked_sub(num_after)?;
+
+        (start..end).find(|index| self.batteries[*index] == digit)
+    }
+
+    /// Selects `num_batteries_to_use` batteries from `start` on in a single pass, dropping the
+    /// last kept battery whenever `should_drop(last, next)` and enough batteries remain.
+    fn select_greedily(
+        &self,
+        start: usize,
+        num_batteries_to_use: usize,
+        should_drop: impl Fn(u8, u8) -> bool,
+    ) -> Vec<usize> {
+        let mut num_to_drop = self.batteries.len() - start - num_batteries_to_use;
+
+        let mut indices: Vec<usize> = Vec::with_capacity(self.batteries.len() - start);
+
+        for (index, &battery) in self.batteries.iter().enumerate().skip(start) {
+            while num_to_drop > 0
+                && indices
+                    .last()
+                    .is_some_and(|last| should_drop(self.batteries[*last], battery))
+            {
+                indices.pop();
+                num_to_drop -= 1;
+            }
+
+            indices.push(index);
+        }
+
+        indices.truncate(num_batteries_to_use);
+
+        indices
+    }
+
+    fn selection_from_indices(&self, indices: Vec<usize>) -> BatterySelection {
+        let digits: Vec<u8> = indices.iter().map(|index| self.batteries[*index]).collect();
+
+        let voltage = Voltage::from_digits(&digits, self.radix);
+
+        BatterySelection {
+            indices,
+            digits,
+            voltage,
+        }
+    }
+
+    /// Shows the bank with a `^` under each selected battery.
+    fn render_selection(&self, selection: &BatterySelection) -> String {
+        let mut batteries = String::new();
+        let mut markers = String::new();
+
+        let mut selected = selection.indices.iter().peekable();
+
+        for (index, battery) in self.batteries.iter().enumerate() {
+            batteries.push(char::from_digit(*battery as u32, self.radix).unwrap());
+
+            if selected.next_if_eq(&&index).is_some() {
+                markers.push('^');
+            } else {
+                markers.push(' ');
+            }
+        }
+
+        format!("{}\n{}", batteries, markers.trim_end())
+    }
+
+    /// Picks the batteries by scanning for the highest remaining one once per selected battery.
+    fn find_highest_voltage_rescanning(
+        &self,
+        num_batteries_to_use: usize,
+    ) -> Result<Voltage, BatteryBankError> {
+        self.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        let mut digits = Vec::new();
+
+        let mut current_index: usize = 0;
+
+        for i in (0..num_batteries_to_use).rev() {
+            let allowed_batteries = &self.batteries[current_index..self.batteries.len() - i];
+
+            // Never empty, as there are always at least i + 1 batteries left
+            let result = BatteryBank::find_highest_digit(
+                allowed_batteries,
+                current_index,
+                &TieBreak::Earliest,
+            )
+            .unwrap();
+
+            digits.push(result.digit);
+            current_index = current_index + result.index + 1;
+        }
+
+        Ok(Voltage::from_digits(&digits, self.radix))
+    }
+
+    #[cfg(test)]
+    fn parse_multiple(battery_lines: &str) -> Vec<Self> {
+        BatteryBank::parse_multiple_with_radix(battery_lines, BatteryBank::DEFAULT_RADIX)
+    }
+
+    /// Reads one bank per line from `reader`, holding no more than a single line at a time.
+    /// Invalid characters are reported as [`io::ErrorKind::InvalidData`] wrapping a
+    /// [`ParseError`].
+    fn read_multiple<R: BufRead>(
+        reader: R,
+        radix: u32,
+        options: ParseOptions,
+    ) -> BatteryBankReader<R> {
+        BatteryBank::check_radix(radix);
+
+        BatteryBankReader {
+            reader,
+            radix,
+            options,
+            line_number: 0,
+        }
+    }
+
+    #[cfg(test)]
+    fn parse_multiple_with_radix(battery_lines: &str, radix: u32) -> Vec<Self> {
+        BatteryBank::try_parse_multiple_with_radix(battery_lines, radix, ParseOptions::default())
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    fn try_parse_multiple_with_radix(
+        battery_lines: &str,
+        radix: u32,
+        options: ParseOptions,
+    ) -> Result<Vec<Self>, ParseError> {
+        let mut battery_banks: Vec<Self> = Vec::new();
+
+        for (i, line) in battery_lines.lines().enumerate() {
+            let battery_bank =
+                BatteryBank::try_parse_with_radix(line, radix, options).map_err(|error| {
+                    ParseError {
+                        line: i + 1,
+                        ..error
+                    }
+                })?;
+
+            battery_banks.push(battery_bank);
+        }
+
+        Ok(battery_banks)
+    }
+}
+
+/// A bank whose batteries each cost something to draw from, written as `digit:cost` pairs
+/// separated by whitespace, such as `9:3 8:1 7:2`.
+struct WeightedBatteryBank {
+    bank: BatteryBank,
+    costs: Vec<u64>,
+}
+
+/// A selection of batteries from a weighted bank, and what drawing from them costs.
+#[derive(PartialEq, Debug)]
+struct WeightedSelection {
+    selection: BatterySelection,
+    cost: u64,
+}
+
+impl WeightedBatteryBank {
+    #[cfg(test)]
+    fn parse(battery_line: &str) -> Self {
+        WeightedBatteryBank::try_parse_with_radix(battery_line, BatteryBank::DEFAULT_RADIX)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Reads the `digit:cost` pairs with digits in `radix` and decimal costs. A pair cut short
+    /// by the end of the line is reported as an invalid line break after it.
+    fn try_parse_with_radix(battery_line: &str, radix: u32) -> Result<Self, ParseError> {
+        BatteryBank::check_radix(radix);
+
+        let invalid = |(index, character): (usize, char)| ParseError {
+            line: 1,
+            column: index + 1,
+            character,
+        };
+        let end_of_line = (battery_line.chars().count(), '\n');
+
+        let mut batteries = Vec::new();
+        let mut costs = Vec::new();
+
+        let mut chars = battery_line.chars().enumerate().peekable();
+
+        loop {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+            let Some((index, c)) = chars.next() else {
+                break;
+            };
+
+            batteries.push(BatteryBank::char_to_digit(c, radix).ok_or(invalid((index, c)))?);
+
+            match chars.next() {
+                Some((_, ':')) => {}
+                other => return Err(invalid(other.unwrap_or(end_of_line))),
+            }
+
+            let mut cost: Option<u64> = None;
+
+            while let Some((index, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+                cost = c
+                    .to_digit(10)
+                    .and_then(|digit| cost.unwrap_or(0).checked_mul(10)?.checked_add(digit as u64));
+
+                if cost.is_none() {
+                    return Err(invalid((index, c)));
+                }
+            }
+
+            costs.push(cost.ok_or(invalid(chars.peek().copied().unwrap_or(end_of_line)))?);
+        }
+
+        Ok(Self {
+            bank: BatteryBank { batteries, radix },
+            costs,
+        })
+    }
+
+    fn try_parse_multiple_with_radix(
+        battery_lines: &str,
+        radix: u32,
+    ) -> Result<Vec<Self>, ParseError> {
+        battery_lines
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                WeightedBatteryBank::try_parse_with_radix(line, radix).map_err(|error| ParseError {
+                    line: i + 1,
+                    ..error
+                })
+            })
+            .collect()
+    }
+
+    /// The least cost of drawing `m` batteries from index `i` on, for every `i` and every `m` up
+    /// to `num_batteries_to_use`. None where there are fewer than `m` batteries left.
+    fn cheapest_table(&self, num_batteries_to_use: usize) -> Vec<Vec<Option<u64>>> {
+        let num_batteries = self.costs.len();
+
+        let mut cheapest: Vec<Vec<Option<u64>>> =
+            vec![vec![None; num_batteries_to_use + 1]; num_batteries + 1];
+
+        for i in (0..=num_batteries).rev() {
+            cheapest[i][0] = Some(0);
+
+            if i == num_batteries {
+                continue;
+            }
+
+            for m in 1..=num_batteries_to_use {
+                let taken = cheapest[i + 1][m - 1].and_then(|rest| rest.checked_add(self.costs[i]));
+
+                cheapest[i][m] = [cheapest[i + 1][m], taken].into_iter().flatten().min();
+            }
+        }
+
+        cheapest
+    }
+
+    /// Finds the highest voltage whose batteries cost at most `max_cost` in total, and the
+    /// cheapest batteries giving it.
+    ///
+    /// Fixes the digits from the first on, each time taking the highest digit for which the
+    /// cheapest way of picking the digits so far still leaves room for the cheapest remaining
+    /// batteries. The cheapest ways of picking the digits so far are kept per last index.
+    fn select_highest_voltage_within_cost(
+        &self,
+        num_batteries_to_use: usize,
+        max_cost: u64,
+    ) -> Result<WeightedSelection, BatteryBankError> {
+        self.bank.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        let num_batteries = self.costs.len();
+        let cheapest = self.cheapest_table(num_batteries_to_use);
+
+        if cheapest[0][num_batteries_to_use].is_none_or(|cost| cost > max_cost) {
+            return Err(BatteryBankError::Infeasible);
+        }
+
+        // For every digit fixed so far, the least cost of picking the digits up to it with it
+        // at each index
+        let mut prefix_costs: Vec<Vec<Option<u64>>> = Vec::with_capacity(num_batteries_to_use);
+
+        for j in 0..num_batteries_to_use {
+            // The least cost of the digits before this one, ending before each index
+            let mut costs_before = Vec::with_capacity(num_batteries);
+            let mut least: Option<u64> = if j == 0 { Some(0) } else { None };
+
+            for i in 0..num_batteries {
+                costs_before.push(least);
+
+                if let Some(previous) = prefix_costs.last() {
+                    least = [least, previous[i]].into_iter().flatten().min();
+                }
+            }
+
+            let num_after = num_batteries_to_use - j - 1;
+
+            // The highest digit that still fits, of which there always is one, as the cheapest
+            // batteries were checked to fit up front
+            let costs = (0..self.bank.radix as u8)
+                .rev()
+                .map(|digit| {
+                    (0..num_batteries)
+                        .map(|i| {
+                            costs_before[i]
+                                .filter(|_| self.bank.batteries[i] == digit)?
+                                .checked_add(self.costs[i])
+                        })
+                        .collect::<Vec<Option<u64>>>()
+                })
+                .find(|costs| {
+                    costs.iter().enumerate().any(|(i, cost)| {
+                        cost.zip(cheapest[i + 1][num_after])
+                            .and_then(|(cost, rest)| cost.checked_add(rest))
+                            .is_some_and(|total| total <= max_cost)
+                    })
+                })
+                .unwrap();
+
+            prefix_costs.push(costs);
+        }
+
+        // Walk back from the cheapest last battery through the cheapest ones before each
+        let mut indices = vec![0; num_batteries_to_use];
+        let mut end = num_batteries;
+
+        for (j, costs) in prefix_costs.iter().enumerate().rev() {
+            let index = (0..end)
+                .filter(|i| costs[*i].is_some())
+                .rev()
+                .min_by_key(|i| costs[*i])
+                .unwrap();
+
+            indices[j] = index;
+            end = index;
+        }
+
+        let cost = indices.iter().map(|index| self.costs[*index]).sum();
+
+        Ok(WeightedSelection {
+            selection: self.bank.selection_from_indices(indices),
+            cost,
+        })
+    }
+
+    /// Finds the cheapest batteries whose voltage is at least `target`.
+    ///
+    /// While the selection so far matches the target's leading digits, the next battery must
+    /// match or beat the target's next digit. Once it beats it, any batteries will do, so the
+    /// cheapest remaining ones are taken.
+    fn select_cheapest_reaching(
+        &self,
+        num_batteries_to_use: usize,
+        target: &Voltage,
+    ) -> Result<WeightedSelection, BatteryBankError> {
+        self.bank.check_num_batteries_to_use(num_batteries_to_use)?;
+
+        let target_digits = target
+            .to_padded_digits(self.bank.radix, num_batteries_to_use)
+            .ok_or(BatteryBankError::Infeasible)?;
+
+        let num_batteries = self.costs.len();
+        let cheapest = self.cheapest_table(num_batteries_to_use);
+
+        // The cost of taking battery i as the next one with m batteries still to pick, given the
+        // selection so far matches the target
+        let taking_cost = |matching: &[Vec<Option<u64>>], i: usize, m: usize| {
+            let target_digit = target_digits[num_batteries_to_use - m];
+
+            let rest = match self.bank.batteries[i].cmp(&target_digit) {
+                Ordering::Greater => cheapest[i + 1][m - 1],
+                Ordering::Equal => matching[i + 1][m - 1],
+                Ordering::Less => None,
+            };
+
+            rest?.checked_add(self.costs[i])
+        };
+
+        // The least cost of picking m batteries from index i on that keep matching or beating
+        // the target, given the selection so far matches it
+        let mut matching = vec![vec![None; num_batteries_to_use + 1]; num_batteries + 1];
+
+        for i in (0..=num_batteries).rev() {
+            matching[i][0] = Some(0);
+
+            if i == num_batteries {
+                continue;
+            }
+
+            for m in 1..=num_batteries_to_use {
+                matching[i][m] = [matching[i + 1][m], taking_cost(&matching, i, m)]
+                    .into_iter()
+                    .flatten()
+                    .min();
+            }
+        }
+
+        let cost = matching[0][num_batteries_to_use].ok_or(BatteryBankError::Infeasible)?;
+
+        let mut indices = Vec::with_capacity(num_batteries_to_use);
+        let mut is_matching = true;
+
+        for i in 0..num_batteries {
+            let m = num_batteries_to_use - indices.len();
+
+            if m == 0 {
+                break;
+            }
+
+            let (taken, least) = if is_matching {
+                (taking_cost(&matching, i, m), matching[i][m])
+            } else {
+                (
+                    cheapest[i + 1][m - 1].and_then(|rest| rest.checked_add(self.costs[i])),
+                    cheapest[i][m],
+                )
+            };
+
+            if taken.is_some() && taken == least {
+                if self.bank.batteries[i] > target_digits[num_batteries_to_use - m] {
+                    is_matching = false;
+                }
+
+                indices.push(i);
+            }
+        }
+
+        Ok(WeightedSelection {
+            selection: self.bank.selection_from_indices(indices),
+            cost,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::{
+        BankLimits, BatteryBank, BatteryBankError, BatterySelection, BudgetAllocation, ParseError,
+        ParseOptions, ParseVoltageError, SelectionConstraint, ShortBankPolicy, TieBreak, Voltage,
+        VoltageObjective, WeightedBatteryBank,
+    };
+
+    const SINGLE_BATTERY_BANK: &str = "987654321111111";
+    const SAMPLE_INPUT: &str = r"987654321111111
+811111111111119
+234234234234278
+818181911112111";
+
+    #[test]
+    fn test_find_highest_joltage() {
+        let battery_bank = BatteryBank::parse(SINGLE_BATTERY_BANK);
+
+        let voltage = battery_bank.find_highest_voltage(2);
+
+        assert_eq!(voltage, Ok(Voltage::from(98)));
+    }
+
+    #[test]
+    fn test_muliple_battery_banks_2() {
+        let battery_banks = BatteryBank::parse_multiple(SAMPLE_INPUT);
+
+        assert_eq!(
+            battery_banks
+                .iter()
+                .map(|b| b.find_highest_voltage(2).unwrap())
+                .collect::<Vec<Voltage>>(),
+            vec![98, 89, 78, 92],
+        );
+    }
+
+    #[test]
+    fn test_muliple_battery_banks_12() {
+        let battery_banks = BatteryBank::parse_multiple(SAMPLE_INPUT);
+
+        assert_eq!(
+            battery_banks
+                .iter()
+                .map(|b| b.find_highest_voltage(12).unwrap())
+                .collect::<Vec<Voltage>>(),
+            vec![987654321111, 811111111119, 434234234278, 888911112111],
+        );
+    }
+
+    #[test]
+    fn test_find_highest_voltage_matches_rescanning() {
+        let battery_banks = BatteryBank::parse_multiple(SAMPLE_INPUT);
+
+        for battery_bank in &battery_banks {
+            for num_batteries_to_use in 1..=battery_bank.batteries.len() {
+                assert_eq!(
+                    battery_bank.find_highest_voltage(num_batteries_to_use),
+                    battery_bank.find_highest_voltage_rescanning(num_batteries_to_use)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_highest_voltage() {
+        let battery_bank = BatteryBank::parse("818181911112111");
+
+        assert_eq!(
+            battery_bank.select_highest_voltage(2),
+            Ok(BatterySelection {
+                indices: vec![6, 11],
+                digits: vec![9, 2],
+                voltage: Voltage::from(92)
+            })
+        );
+        assert_eq!(
+            battery_bank.select_highest_voltage(12).unwrap().indices,
+            vec![0, 2, 4, 6, 7, 8, 9, 10, 11, 12, 13, 14]
+        );
+    }
+
+    #[test]
+    fn test_render_selection() {
+        let battery_bank = BatteryBank::parse("234234234234278");
+
+        let selection = battery_bank.select_highest_voltage(2).unwrap();
+
+        assert_eq!(
+            battery_bank.render_selection(&selection),
+            "234234234234278\n             ^^"
+        );
+    }
+
+    #[test]
+    fn test_too_many_batteries_to_use() {
+        let battery_bank = BatteryBank::parse("8119");
+
+        assert_eq!(
+            battery_bank.find_highest_voltage(5),
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 4,
+                requested: 5
+            })
+        );
+        assert_eq!(
+            battery_bank.find_highest_voltage_rescanning(5),
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 4,
+                requested: 5
+            })
+        );
+        assert_eq!(
+            battery_bank.find_highest_voltage(4),
+            Ok(Voltage::from(8119))
+        );
+        assert_eq!(battery_bank.find_highest_voltage(0), Ok(Voltage::from(0)));
+    }
+
+    #[test]
+    fn test_empty_battery_bank() {
+        let battery_banks = BatteryBank::parse_multiple("811\n\n234");
+
+        assert_eq!(
+            battery_banks[1].find_highest_voltage(2),
+            Err(BatteryBankError::EmptyBank)
+        );
+        assert_eq!(
+            battery_banks[1].find_highest_voltage_rescanning(2),
+            Err(BatteryBankError::EmptyBank)
+        );
+        assert!(BatteryBank::find_highest_digit(&[], 0, &TieBreak::Earliest).is_none());
+    }
+
+    #[test]
+    fn test_short_bank_policies() {
+        let battery_banks = BatteryBank::parse_multiple("811\n\n93\n234");
+
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(&battery_banks, &[3], ShortBankPolicy::Skip),
+            vec![Ok(Voltage::from(811 + 234))]
+        );
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[3],
+                ShortBankPolicy::UseAll
+            ),
+            vec![Ok(Voltage::from(811 + 93 + 234))]
+        );
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[3],
+                ShortBankPolicy::Error
+            ),
+            vec![Err(BatteryBankError::EmptyBank)]
+        );
+        assert_eq!(
+            battery_banks[2].find_highest_voltage_with_policy(3, ShortBankPolicy::Error),
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 2,
+                requested: 3
+            })
+        );
+        assert_eq!(
+            battery_banks[0].find_highest_voltage_with_policy(2, ShortBankPolicy::Skip),
+            Ok(Some(Voltage::from(81)))
+        );
+    }
+
+    #[test]
+    fn test_voltage() {
+        assert_eq!(Voltage::from(0).to_string(), "0");
+        assert_eq!(Voltage::from(u64::MAX).to_string(), u64::MAX.to_string());
+        assert_eq!(Voltage::from(1_000_000_007).to_u64(), Some(1_000_000_007));
+        assert_eq!(Voltage::from_digits(&[1, 0, 1, 1], 2), Voltage::from(11));
+        assert_eq!(Voltage::from_digits(&[1; 64], 2), Voltage::from(u64::MAX));
+        assert_eq!(Voltage::from_digits(&[0, 0, 7], 10), Voltage::from(7));
+        assert_eq!(Voltage::from_digits(&[0; 20], 10), Voltage::from(0));
+        assert_eq!(
+            Voltage::from(0x2a).to_padded_digits(16, 4),
+            Some(vec![0, 0, 2, 10])
+        );
+        assert_eq!(Voltage::from(0x2a).to_padded_digits(16, 1), None);
 
-    let sum: u64 = battery_banks
-        .iter()
-        .map(|bb| bb.find_highest_voltage(12))
-        .sum();
+        let big = Voltage::from_digits(&[9; 40], 10);
+        assert_eq!(big.to_string(), "9".repeat(40));
+        assert_eq!(big.to_u64(), None);
+        assert_eq!(
+            (big.clone() + &Voltage::from(1)).to_string(),
+            format!("1{}", "0".repeat(40))
+        );
+        assert_eq!(big.cmp(&Voltage::from(u64::MAX)), Ordering::Greater);
+        assert_eq!(Voltage::from(9).cmp(&Voltage::from(10)), Ordering::Less);
+        assert_eq!(
+            Voltage::from(1_000_000_001).cmp(&Voltage::from(999_999_999)),
+            Ordering::Greater
+        );
 
-    println!("The sum was: {}", sum);
-}
+        assert_eq!("007".parse(), Ok(Voltage::from(7)));
+        assert_eq!(
+            format!("1{}", "0".repeat(40)).parse(),
+            Ok(big.clone() + &Voltage::from(1))
+        );
+        for invalid in ["", "-1", "+1", "1_000", "12a"] {
+            assert_eq!(
+                invalid.parse::<Voltage>(),
+                Err(ParseVoltageError),
+                "{invalid:?}"
+            );
+        }
 
-struct BatteryBank {
-    batteries: Vec<u64>,
-}
+        assert_eq!(
+            [Voltage::from(1), Voltage::from(2), Voltage::from(3)]
+                .into_iter()
+                .sum::<Voltage>(),
+            Voltage::from(6)
+        );
+    }
 
-struct FindDigitResult {
-    digit: u64,
-    index: usize,
-}
+    #[test]
+    fn test_arbitrary_length_voltages() {
+        let battery_banks =
+            BatteryBank::parse_multiple(&format!("{}\n{}", "9".repeat(100), "8".repeat(100)));
 
-impl BatteryBank {
-    const BATTERY_BANK_RADIX: u32 = 10;
+        assert_eq!(
+            battery_banks[0]
+                .find_highest_voltage(30)
+                .unwrap()
+                .to_string(),
+            "9".repeat(30)
+        );
+        assert_eq!(
+            battery_banks[0].find_highest_voltage_rescanning(30),
+            battery_banks[0].find_highest_voltage(30)
+        );
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[100],
+                ShortBankPolicy::Error
+            ),
+            vec![Ok(format!("1{}7", "8".repeat(99)).parse().unwrap())]
+        );
+    }
 
-    fn parse(battery_line: &str) -> Self {
-        Self {
-            batteries: battery_line
-                .chars()
-                .map(|c| BatteryBank::char_to_digit(c))
-                .collect(),
-        }
+    /// Every way of selecting `k` batteries, as voltages.
+    fn all_voltages(battery_bank: &BatteryBank, k: usize) -> Vec<(bool, u64)> {
+        let n = battery_bank.batteries.len();
+
+        (0u32..1 << n)
+            .filter(|mask| mask.count_ones() as usize == k)
+            .map(|mask| {
+                let digits: Vec<u64> = (0..n)
+                    .filter(|i| mask & (1 << i) != 0)
+                    .map(|i| battery_bank.batteries[i] as u64)
+                    .collect();
+
+                let has_leading_zero = digits.first() == Some(&0);
+                let voltage = digits.iter().fold(0, |voltage, digit| voltage * 10 + digit);
+
+                (has_leading_zero, voltage)
+            })
+            .collect()
     }
 
-    fn find_highest_digit(digits: &[u64]) -> FindDigitResult {
-        let mut highest = FindDigitResult {
-            digit: digits[0],
-            index: 0,
-        };
+    #[test]
+    fn test_voltage_objectives_match_brute_force() {
+        let battery_banks = BatteryBank::parse_multiple("9071302845\n0010\n5555\n120340");
 
-        for (index, digit) in digits.iter().enumerate() {
-            if *digit > highest.digit {
-                highest = FindDigitResult {
-                    digit: *digit,
-                    index,
+        for battery_bank in &battery_banks {
+            for k in 1..=battery_bank.batteries.len() {
+                let voltages = all_voltages(battery_bank, k);
+
+                let select = |objective: VoltageObjective| {
+                    battery_bank
+                        .select_voltage(k, &objective)
+                        .ok()
+                        .map(|selection| selection.voltage.to_u64().unwrap())
+                };
+
+                assert_eq!(
+                    select(VoltageObjective::Lowest {
+                        allow_leading_zero: true
+                    }),
+                    voltages.iter().map(|(_, voltage)| *voltage).min()
+                );
+                assert_eq!(
+                    select(VoltageObjective::Lowest {
+                        allow_leading_zero: false
+                    }),
+                    voltages
+                        .iter()
+                        .filter(|(has_leading_zero, _)| !has_leading_zero)
+                        .map(|(_, voltage)| *voltage)
+                        .min()
+                );
+
+                for threshold in [0, 7, 100, 3000, 45678, 999999] {
+                    assert_eq!(
+                        select(VoltageObjective::HighestAtMost(Voltage::from(threshold))),
+                        voltages
+                            .iter()
+                            .map(|(_, voltage)| *voltage)
+                            .filter(|voltage| *voltage <= threshold)
+                            .max(),
+                        "at most {threshold} with {k} batteries"
+                    );
+                }
+
+                for divisor in [1, 2, 7, 13, 100] {
+                    assert_eq!(
+                        select(VoltageObjective::HighestDivisibleBy(divisor)),
+                        voltages
+                            .iter()
+                            .map(|(_, voltage)| *voltage)
+                            .filter(|voltage| voltage % divisor == 0)
+                            .max(),
+                        "divisible by {divisor} with {k} batteries"
+                    );
                 }
             }
         }
+    }
+
+    #[test]
+    fn test_lowest_voltage() {
+        let battery_bank = BatteryBank::parse("9071302845");
 
-        highest
+        assert_eq!(
+            battery_bank
+                .select_lowest_voltage(3, false)
+                .unwrap()
+                .indices,
+            vec![3, 5, 6]
+        );
+        assert_eq!(
+            battery_bank.select_lowest_voltage(3, true).unwrap().voltage,
+            2
+        );
+        assert_eq!(
+            BatteryBank::parse("0001").select_lowest_voltage(2, false),
+            Err(BatteryBankError::Infeasible)
+        );
     }
 
-    fn char_to_digit(c: char) -> u64 {
-        c.to_digit(BatteryBank::BATTERY_BANK_RADIX).unwrap() as u64
+    #[test]
+    fn test_parse_objectives() {
+        assert_eq!(
+            VoltageObjective::parse("lowest-with-zero"),
+            Some(VoltageObjective::Lowest {
+                allow_leading_zero: true
+            })
+        );
+        assert_eq!(
+            VoltageObjective::parse("at-most:500"),
+            Some(VoltageObjective::HighestAtMost(Voltage::from(500)))
+        );
+        assert_eq!(
+            VoltageObjective::parse("at-most:18446744073709551616"),
+            Some(VoltageObjective::HighestAtMost(
+                Voltage::from(u64::MAX) + &Voltage::from(1)
+            ))
+        );
+        assert_eq!(
+            VoltageObjective::parse("divisible-by:7"),
+            Some(VoltageObjective::HighestDivisibleBy(7))
+        );
+        assert_eq!(VoltageObjective::parse("divisible-by:x"), None);
+        assert_eq!(VoltageObjective::parse("at-least:5"), None);
     }
 
-    fn find_highest_voltage(&self, num_batteries_to_use: usize) -> u64 {
-        let mut number = String::new();
+    #[test]
+    fn test_parse_constraints() {
+        assert_eq!(
+            SelectionConstraint::parse("contiguous"),
+            Some(SelectionConstraint::Contiguous)
+        );
+        assert_eq!(
+            SelectionConstraint::parse("min-spacing:4"),
+            Some(SelectionConstraint::MinSpacing(4))
+        );
+        assert_eq!(SelectionConstraint::parse("contiguous:2"), None);
+    }
 
-        let mut current_index: usize = 0;
+    #[test]
+    fn test_infeasible_objectives() {
+        let battery_bank = BatteryBank::parse("5555");
 
-        for i in (0..num_batteries_to_use).rev() {
-            let allowed_batteries = &self.batteries[current_index..self.batteries.len() - i];
+        assert_eq!(
+            battery_bank.select_highest_voltage_at_most(2, &Voltage::from(54)),
+            Err(BatteryBankError::Infeasible)
+        );
+        assert_eq!(
+            battery_bank.select_highest_voltage_divisible_by(2, 2),
+            Err(BatteryBankError::Infeasible)
+        );
+        assert_eq!(
+            battery_bank.select_highest_voltage_divisible_by(2, 0),
+            Err(BatteryBankError::Infeasible)
+        );
+    }
 
-            let result = BatteryBank::find_highest_digit(allowed_batteries);
+    #[test]
+    fn test_highest_voltage_divisible_by_large_divisor() {
+        // The first 100 digits of pi
+        let battery_bank = BatteryBank::parse(
+            "3141592653589793238462643383279502884197169399375105820974944592307816406286208998628034825342117067",
+        );
 
-            number.push_str(&result.digit.to_string());
-            current_index = current_index + result.index + 1;
+        for (divisor, expected) in [(999_983, 999_999_999_711), (1_000_003, 999_999_999_991)] {
+            assert_eq!(
+                battery_bank
+                    .select_highest_voltage_divisible_by(12, divisor)
+                    .map(|selection| selection.voltage),
+                Ok(Voltage::from(expected))
+            );
         }
 
-        number.parse::<u64>().unwrap()
+        for divisor in [1 << 40, u64::MAX] {
+            assert_eq!(
+                battery_bank.select_highest_voltage_divisible_by(12, divisor),
+                Err(BatteryBankError::DivisorTooLarge { divisor })
+            );
+        }
     }
 
-    fn parse_multiple(battery_lines: &str) -> Vec<Self> {
-        let mut battery_banks: Vec<Self> = Vec::new();
+    #[test]
+    fn test_sweep_highest_voltages() {
+        let battery_banks = BatteryBank::parse_multiple(&format!(
+            "{SAMPLE_INPUT}\n9071302845\n5555\n1\n\n123456789\n987654321"
+        ));
+
+        for battery_bank in &battery_banks {
+            let sweep = battery_bank.sweep_highest_voltages();
 
-        for line in battery_lines.lines() {
-            battery_banks.push(BatteryBank::parse(line));
+            assert_eq!(sweep.len(), battery_bank.batteries.len() + 1);
+
+            for (num_batteries_to_use, voltage) in sweep.iter().enumerate() {
+                assert_eq!(
+                    Ok(voltage),
+                    battery_bank
+                        .find_highest_voltage(num_batteries_to_use)
+                        .as_ref()
+                );
+            }
         }
+    }
+
+    #[test]
+    fn test_sweep_totals() {
+        let battery_banks = BatteryBank::parse_multiple("811\n93\n234");
 
-        battery_banks
+        assert_eq!(
+            BatteryBank::sweep_totals(&battery_banks, ShortBankPolicy::Skip),
+            vec![
+                Ok(Voltage::from(8 + 9 + 4)),
+                Ok(Voltage::from(81 + 93 + 34)),
+                Ok(Voltage::from(811 + 234))
+            ]
+        );
+        assert_eq!(
+            BatteryBank::sweep_totals(&battery_banks, ShortBankPolicy::UseAll)[2],
+            Ok(Voltage::from(811 + 93 + 234))
+        );
+        assert_eq!(
+            BatteryBank::sweep_totals(&battery_banks, ShortBankPolicy::Error)[2],
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 2,
+                requested: 3
+            })
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::BatteryBank;
+    #[test]
+    fn test_radix() {
+        let battery_bank = BatteryBank::parse_with_radix("1f0a9B", 16);
 
-    const SINGLE_BATTERY_BANK: &str = "987654321111111";
-    const SAMPLE_INPUT: &str = r"987654321111111
-811111111111119
-234234234234278
-818181911112111";
+        assert_eq!(battery_bank.batteries, vec![1, 15, 0, 10, 9, 11]);
+
+        let voltage = battery_bank.find_highest_voltage(3).unwrap();
+        assert_eq!(voltage, Voltage::from(0xfab));
+        assert_eq!(voltage.to_radix_string(16), "fab");
+        assert_eq!(Voltage::from(0).to_radix_string(16), "0");
+
+        let selection = battery_bank.select_highest_voltage(2).unwrap();
+        assert_eq!(battery_bank.render_selection(&selection), "1f0a9b\n ^   ^");
+
+        assert_eq!(
+            battery_bank
+                .select_highest_voltage_divisible_by(2, 5)
+                .map(|selection| selection.voltage),
+            Ok(Voltage::from(0xfa))
+        );
+
+        let battery_banks = BatteryBank::parse_multiple_with_radix("1011\n0110", 2);
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[3],
+                ShortBankPolicy::Error
+            ),
+            vec![Ok(Voltage::from(0b111 + 0b110))]
+        );
+    }
 
     #[test]
-    fn test_find_highest_joltage() {
-        let battery_bank = BatteryBank::parse(SINGLE_BATTERY_BANK);
+    fn test_constrained_voltages_match_brute_force() {
+        let battery_banks = BatteryBank::parse_multiple("9071302845\n5555\n120340\n818181911112");
 
-        let voltage = battery_bank.find_highest_voltage(2);
+        let constraints = [
+            SelectionConstraint::MaxGap(0),
+            SelectionConstraint::MaxGap(1),
+            SelectionConstraint::MaxGap(3),
+            SelectionConstraint::MinSpacing(0),
+            SelectionConstraint::MinSpacing(2),
+            SelectionConstraint::MinSpacing(4),
+            SelectionConstraint::Contiguous,
+        ];
+
+        for battery_bank in &battery_banks {
+            let n = battery_bank.batteries.len();
+
+            for k in 1..=n {
+                for constraint in &constraints {
+                    let (min_gap, max_gap) = constraint.gap_bounds();
+
+                    let expected = (0u32..1 << n)
+                        .filter(|mask| mask.count_ones() as usize == k)
+                        .map(|mask| (0..n).filter(|i| mask & (1 << i) != 0).collect::<Vec<_>>())
+                        .filter(|indices| {
+                            indices
+                                .windows(2)
+                                .all(|pair| (min_gap..=max_gap).contains(&(pair[1] - pair[0])))
+                        })
+                        .map(|indices| {
+                            indices.iter().fold(0, |voltage, i| {
+                                voltage * 10 + battery_bank.batteries[*i] as u64
+                            })
+                        })
+                        .max();
+
+                    assert_eq!(
+                        battery_bank
+                            .find_highest_voltage_with_constraint(k, constraint)
+                            .map(|voltage| voltage.to_u64().unwrap()),
+                        expected.ok_or(BatteryBankError::Infeasible),
+                        "{constraint:?} k={k} in {:?}",
+                        battery_bank.batteries
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_constrained_selection() {
+        let battery_bank = BatteryBank::parse("9071302845");
+
+        let selection = battery_bank
+            .select_highest_voltage_with_constraint(3, &SelectionConstraint::Contiguous)
+            .unwrap();
+        assert_eq!(selection.indices, vec![0, 1, 2]);
+        assert_eq!(selection.voltage, Voltage::from(907));
+
+        let selection = battery_bank
+            .select_highest_voltage_with_constraint(3, &SelectionConstraint::MinSpacing(4))
+            .unwrap();
+        assert_eq!(selection.indices, vec![0, 4, 9]);
 
-        assert_eq!(voltage, 98);
+        assert_eq!(
+            battery_bank
+                .select_highest_voltage_with_constraint(4, &SelectionConstraint::MinSpacing(4)),
+            Err(BatteryBankError::Infeasible)
+        );
+        assert_eq!(
+            battery_bank.find_highest_voltage_with_constraint(11, &SelectionConstraint::Contiguous),
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 10,
+                requested: 11
+            })
+        );
     }
 
     #[test]
-    fn test_muliple_battery_banks_2() {
-        let battery_banks = BatteryBank::parse_multiple(SAMPLE_INPUT);
+    fn test_tie_break() {
+        let battery_bank = BatteryBank::parse("99899");
+
+        let select = |tie_break: TieBreak| {
+            battery_bank
+                .select_highest_voltage_with_tie_break(2, &tie_break)
+                .map(|selection| selection.indices)
+        };
+
+        assert_eq!(select(TieBreak::Earliest), Ok(vec![0, 1]));
+        assert_eq!(select(TieBreak::Latest), Ok(vec![3, 4]));
+        assert_eq!(
+            select(TieBreak::LeastWorn(vec![5, 1, 0, 3, 2])),
+            Ok(vec![1, 4])
+        );
+
+        for wear in [vec![1], vec![0; 6]] {
+            assert_eq!(
+                select(TieBreak::LeastWorn(wear.clone())),
+                Err(BatteryBankError::WearCountMismatch {
+                    batteries: 5,
+                    wear_counts: wear.len()
+                })
+            );
+        }
+
+        // Picking the later nine first would leave only a one to follow it
+        let battery_bank = BatteryBank::parse("991");
+        assert_eq!(
+            battery_bank
+                .select_highest_voltage_with_tie_break(2, &TieBreak::Latest)
+                .map(|selection| selection.indices),
+            Ok(vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn test_tie_break_keeps_voltage_highest() {
+        let battery_banks =
+            BatteryBank::parse_multiple(&format!("{SAMPLE_INPUT}\n9071302845\n5555"));
+
+        for battery_bank in &battery_banks {
+            let n = battery_bank.batteries.len();
+            let wear: Vec<u64> = (0..n as u64).map(|i| i * 7 % 5).collect();
+
+            for k in 1..=n {
+                let highest = battery_bank.select_highest_voltage(k).unwrap();
+
+                let earliest = battery_bank
+                    .select_highest_voltage_with_tie_break(k, &TieBreak::Earliest)
+                    .unwrap();
+                assert_eq!(earliest, highest);
+
+                let latest = battery_bank
+                    .select_highest_voltage_with_tie_break(k, &TieBreak::Latest)
+                    .unwrap();
+                assert_eq!(latest.voltage, highest.voltage);
+
+                // No other selection with the same digits sits later in the bank
+                let mut end = n;
+                for (j, digit) in latest.digits.iter().enumerate().rev() {
+                    end = battery_bank.batteries[..end]
+                        .iter()
+                        .rposition(|battery| battery == digit)
+                        .unwrap();
+                    assert_eq!(latest.indices[j], end);
+                }
+
+                let least_worn = battery_bank
+                    .select_highest_voltage_with_tie_break(k, &TieBreak::LeastWorn(wear.clone()))
+                    .unwrap();
+                assert_eq!(least_worn.voltage, highest.voltage);
+                assert!(least_worn.indices.windows(2).all(|pair| pair[0] < pair[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocate_budget_matches_brute_force() {
+        let battery_banks = BatteryBank::parse_multiple("811\n93\n2345\n\n7");
+        let limits = [
+            BankLimits { min: 1, max: 2 },
+            BankLimits { min: 0, max: 5 },
+            BankLimits { min: 1, max: 4 },
+            BankLimits { min: 0, max: 1 },
+            BankLimits { min: 0, max: 1 },
+        ];
+
+        for budget in 0..=12 {
+            let mut expected: Option<u64> = None;
+
+            for counts in
+                (0..5usize.pow(5)).map(|n| [n % 5, n / 5 % 5, n / 25 % 5, n / 125 % 5, n / 625])
+            {
+                let fits = counts.iter().zip(&battery_banks).zip(&limits).all(
+                    |((count, battery_bank), limits)| {
+                        (limits.min..=limits.max).contains(count)
+                            && *count <= battery_bank.batteries.len()
+                    },
+                );
+
+                if !fits || counts.iter().sum::<usize>() > budget {
+                    continue;
+                }
+
+                let sum = counts
+                    .iter()
+                    .zip(&battery_banks)
+                    .map(|(count, battery_bank)| {
+                        battery_bank
+                            .find_highest_voltage(*count)
+                            .unwrap()
+                            .to_u64()
+                            .unwrap()
+                    })
+                    .sum();
+
+                expected = expected.max(Some(sum));
+            }
+
+            let allocation = BatteryBank::allocate_budget(&battery_banks, &limits, budget);
+
+            assert_eq!(
+                allocation
+                    .as_ref()
+                    .map(|allocation| allocation.total.to_u64().unwrap()),
+                expected.ok_or(&BatteryBankError::Infeasible),
+                "budget {budget}"
+            );
+
+            if let Ok(allocation) = allocation {
+                assert!(allocation.counts.iter().sum::<usize>() <= budget);
+                assert_eq!(
+                    allocation
+                        .counts
+                        .iter()
+                        .zip(&battery_banks)
+                        .map(|(count, battery_bank)| battery_bank
+                            .find_highest_voltage(*count)
+                            .unwrap())
+                        .sum::<Voltage>(),
+                    allocation.total
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_allocate_budget() {
+        let battery_banks = BatteryBank::parse_multiple("811\n93");
+        let limits = [BankLimits { min: 0, max: 3 }; 2];
+
+        assert_eq!(
+            BatteryBank::allocate_budget(&battery_banks, &limits, 3),
+            Ok(BudgetAllocation {
+                counts: vec![3, 0],
+                total: Voltage::from(811)
+            })
+        );
+        assert_eq!(
+            BatteryBank::allocate_budget(&battery_banks, &limits, usize::MAX),
+            Ok(BudgetAllocation {
+                counts: vec![3, 2],
+                total: Voltage::from(811 + 93)
+            })
+        );
+        assert_eq!(
+            BatteryBank::allocate_budget(&battery_banks, &[BankLimits { min: 3, max: 3 }; 2], 6),
+            Err(BatteryBankError::NotEnoughBatteries {
+                available: 2,
+                requested: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_multiple() {
+        let battery_banks =
+            BatteryBank::read_multiple(SAMPLE_INPUT.as_bytes(), 10, ParseOptions::default())
+                .collect::<Result<Vec<BatteryBank>, _>>()
+                .unwrap();
 
         assert_eq!(
             battery_banks
                 .iter()
-                .map(|b| b.find_highest_voltage(2))
-                .collect::<Vec<u64>>(),
-            vec![98, 89, 78, 92],
+                .map(|battery_bank| &battery_bank.batteries)
+                .collect::<Vec<_>>(),
+            BatteryBank::parse_multiple(SAMPLE_INPUT)
+                .iter()
+                .map(|battery_bank| &battery_bank.batteries)
+                .collect::<Vec<_>>()
+        );
+
+        let battery_banks =
+            BatteryBank::read_multiple("811\r\n\n93\n".as_bytes(), 10, ParseOptions::default())
+                .collect::<Result<Vec<BatteryBank>, _>>()
+                .unwrap();
+
+        assert_eq!(battery_banks.len(), 3);
+        assert_eq!(battery_banks[0].batteries, vec![8, 1, 1]);
+        assert!(battery_banks[1].batteries.is_empty());
+
+        let sums = BatteryBank::sum_highest_voltages_for_each(
+            BatteryBank::read_multiple(SAMPLE_INPUT.as_bytes(), 10, ParseOptions::default())
+                .map(Result::unwrap),
+            &[12],
+            ShortBankPolicy::Error,
         );
+        assert_eq!(sums, vec![Ok(Voltage::from(3121910778619))]);
     }
 
     #[test]
-    fn test_muliple_battery_banks_12() {
+    fn test_parse_errors() {
+        let options = ParseOptions::default();
+
+        assert_eq!(
+            BatteryBank::try_parse_multiple_with_radix("811\n9x3", 10, options).err(),
+            Some(ParseError {
+                line: 2,
+                column: 2,
+                character: 'x'
+            })
+        );
+        assert_eq!(
+            BatteryBank::try_parse_multiple_with_radix("811\n93 ", 10, options).err(),
+            Some(ParseError {
+                line: 2,
+                column: 3,
+                character: ' '
+            })
+        );
+        assert_eq!(
+            BatteryBank::try_parse_multiple_with_radix("81é1", 10, options)
+                .err()
+                .map(|error| error.to_string()),
+            Some("Invalid battery 'é' at line 1, column 3".to_string())
+        );
+
+        // A final line keeps its carriage return without a line feed after it
+        let error = BatteryBank::try_parse_multiple_with_radix("811\r\n93\r", 10, options)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid battery '\\r' at line 2, column 3"
+        );
+
+        let error = BatteryBank::read_multiple("811\n\n12a\n".as_bytes(), 10, options)
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(
+            error.into_inner().unwrap().to_string(),
+            "Invalid battery 'a' at line 3, column 3"
+        );
+    }
+
+    #[test]
+    fn test_parse_normalized() {
+        let options = ParseOptions {
+            strip_carriage_return: true,
+            trim_whitespace: true,
+        };
+
+        let battery_banks =
+            BatteryBank::try_parse_multiple_with_radix(" 811 \r\n\t93\r", 10, options).unwrap();
+        assert_eq!(battery_banks[0].batteries, vec![8, 1, 1]);
+        assert_eq!(battery_banks[1].batteries, vec![9, 3]);
+
+        let battery_banks = BatteryBank::read_multiple("  811\r\r\n93 \n".as_bytes(), 10, options)
+            .collect::<Result<Vec<BatteryBank>, _>>()
+            .unwrap();
+        assert_eq!(battery_banks[0].batteries, vec![8, 1, 1]);
+        assert_eq!(battery_banks[1].batteries, vec![9, 3]);
+
+        assert_eq!(
+            BatteryBank::try_parse_multiple_with_radix("8 1", 10, options).err(),
+            Some(ParseError {
+                line: 1,
+                column: 2,
+                character: ' '
+            })
+        );
+    }
+
+    #[test]
+    fn test_sum_highest_voltages_for_each() {
         let battery_banks = BatteryBank::parse_multiple(SAMPLE_INPUT);
 
         assert_eq!(
-            battery_banks
-                .iter()
-                .map(|b| b.find_highest_voltage(12))
-                .collect::<Vec<u64>>(),
-            vec![987654321111, 811111111119, 434234234278, 888911112111],
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[2, 12, 16, 0],
+                ShortBankPolicy::Error
+            ),
+            vec![
+                Ok(Voltage::from(357)),
+                Ok(Voltage::from(3121910778619)),
+                Err(BatteryBankError::NotEnoughBatteries {
+                    available: 15,
+                    requested: 16
+                }),
+                Ok(Voltage::from(0))
+            ]
+        );
+
+        let battery_banks = BatteryBank::parse_multiple("811\n93");
+
+        assert_eq!(
+            BatteryBank::sum_highest_voltages_for_each(
+                &battery_banks,
+                &[2, 3],
+                ShortBankPolicy::Skip
+            ),
+            vec![Ok(Voltage::from(81 + 93)), Ok(Voltage::from(811))]
         );
     }
+
+    #[test]
+    fn test_parse_weighted() {
+        let weighted_bank = WeightedBatteryBank::parse(" 9:3  8:10\t7:0 ");
+
+        assert_eq!(weighted_bank.bank.batteries, vec![9, 8, 7]);
+        assert_eq!(weighted_bank.costs, vec![3, 10, 0]);
+
+        let error_at = |line: &str| {
+            WeightedBatteryBank::try_parse_multiple_with_radix(line, 10)
+                .err()
+                .map(|error| (error.line, error.column, error.character))
+        };
+
+        assert_eq!(error_at("9:3\n8:1 x:2"), Some((2, 5, 'x')));
+        assert_eq!(error_at("9:3 8;1"), Some((1, 6, ';')));
+        assert_eq!(error_at("9:3 8:1a"), Some((1, 8, 'a')));
+        assert_eq!(error_at("9:3 8: 7:1"), Some((1, 7, ' ')));
+        assert_eq!(error_at("9:3 8"), Some((1, 6, '\n')));
+        assert_eq!(error_at("9:99999999999999999999"), Some((1, 22, '9')));
+        assert!(error_at("").is_none());
+    }
+
+    #[test]
+    fn test_weighted_solvers_match_brute_force() {
+        let weighted_banks = WeightedBatteryBank::try_parse_multiple_with_radix(
+            "9:5 0:1 7:2 1:1 3:4 0:0 2:2 8:3 4:1 5:2\n5:1 5:2 5:1 5:3\n1:0 2:9 0:1 3:3 4:0 0:2",
+            10,
+        )
+        .unwrap();
+
+        for weighted_bank in &weighted_banks {
+            let n = weighted_bank.costs.len();
+
+            for k in 1..=n {
+                // Every selection of k batteries as its voltage and cost
+                let selections: Vec<(u64, u64)> = (0u32..1 << n)
+                    .filter(|mask| mask.count_ones() as usize == k)
+                    .map(|mask| {
+                        (0..n).filter(|i| mask & (1 << i) != 0).fold(
+                            (0, 0),
+                            |(voltage, cost), i| {
+                                (
+                                    voltage * 10 + weighted_bank.bank.batteries[i] as u64,
+                                    cost + weighted_bank.costs[i],
+                                )
+                            },
+                        )
+                    })
+                    .collect();
+
+                for max_cost in 0..=20 {
+                    let expected = selections
+                        .iter()
+                        .filter(|(_, cost)| *cost <= max_cost)
+                        .map(|(voltage, _)| *voltage)
+                        .max();
+
+                    let found = weighted_bank.select_highest_voltage_within_cost(k, max_cost);
+
+                    assert_eq!(
+                        found
+                            .as_ref()
+                            .map(|found| found.selection.voltage.to_u64().unwrap()),
+                        expected.ok_or(&BatteryBankError::Infeasible),
+                        "k={k} max_cost={max_cost}"
+                    );
+
+                    if let Ok(found) = found {
+                        let cost: u64 = found
+                            .selection
+                            .indices
+                            .iter()
+                            .map(|i| weighted_bank.costs[*i])
+                            .sum();
+
+                        assert_eq!(found.cost, cost);
+                        assert!(cost <= max_cost);
+                    }
+                }
+
+                for target in [0, 5, 42, 555, 905, 3000, 99999] {
+                    let expected = selections
+                        .iter()
+                        .filter(|(voltage, _)| *voltage >= target)
+                        .map(|(_, cost)| *cost)
+                        .min();
+
+                    let found = weighted_bank.select_cheapest_reaching(k, &Voltage::from(target));
+
+                    assert_eq!(
+                        found.as_ref().map(|found| found.cost),
+                        expected.ok_or(&BatteryBankError::Infeasible),
+                        "k={k} target={target}"
+                    );
+
+                    if let Ok(found) = found {
+                        assert_eq!(found.selection.indices.len(), k);
+                        assert!(found.selection.voltage.to_u64().unwrap() >= target);
+                        assert_eq!(
+                            found.cost,
+                            found
+                                .selection
+                                .indices
+                                .iter()
+                                .map(|i| weighted_bank.costs[*i])
+                                .sum::<u64>()
+                        );
+                    }
+                }
+            }
+        }
+    }
 }